@@ -5,6 +5,7 @@ use const_format::formatcp;
 
 use crate::consts::{CRATE_NAME, CRATE_REPOSITORY, SHORT_VERSION};
 use crate::display::format_date;
+use crate::error::Result;
 use crate::pipeline::Stage;
 use crate::quotes::Quotes;
 
@@ -12,6 +13,10 @@ use crate::quotes::Quotes;
 pub struct Banner {
     pub quotes: Option<Quotes>,
     pub deterministic: bool,
+
+    /// The revision the source files were read from, if they did not come
+    /// from the working tree.
+    pub revision: Option<String>,
 }
 
 impl Banner {
@@ -38,6 +43,7 @@ impl Banner {
         let line1 = formatcp!("{CRATE_NAME} {SHORT_VERSION}");
         let line2 = formatcp!("{CRATE_REPOSITORY}");
         let line3 = format!("Generated at: {}", generated_at);
+        let line4 = self.revision.as_ref().map(|x| format!("Revision: {x}"));
 
         let art_width = ART.iter().map(|x| x.len()).max().unwrap();
         let banner_width = MIN_WIDTH.max(art_width).max(line1.len()).max(line2.len()) + PADDING;
@@ -53,6 +59,9 @@ impl Banner {
         writeln!(out, " * {:^1$}", line2, banner_width)?;
         writeln!(out, " *")?;
         writeln!(out, " * {:^1$}", line3, banner_width)?;
+        if let Some(line4) = line4 {
+            writeln!(out, " * {:^1$}", line4, banner_width)?;
+        }
         writeln!(out, " *")?;
 
         if let Some(quotes) = self.quotes.as_ref() {
@@ -76,7 +85,7 @@ impl Stage for Banner {
         "banner"
     }
 
-    fn process(&mut self, code: String) -> eyre::Result<String> {
+    fn process(&mut self, code: String) -> Result<String> {
         const ESTIMATED_BANNER_SIZE: usize = 1024;
 
         let mut out = String::with_capacity(ESTIMATED_BANNER_SIZE + code.len());
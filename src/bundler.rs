@@ -1,44 +1,442 @@
-use std::fmt::Write;
+use std::fmt::{self, Write};
+use std::path::Path;
 
-use eyre::Result;
+use serde::{Deserialize, Serialize};
 
-use crate::source::Sources;
+use crate::line_markers::{write_end_marker, write_marker};
+use crate::source::{Source, SourceKind, Sources};
+
+/// What kind of bundle to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// One `.c` file that can be compiled on its own.
+    #[default]
+    Source,
+
+    /// One header in the style of the stb libraries. The entry is the public
+    /// header of the library. The implementation is only compiled where
+    /// `<NAME>_IMPLEMENTATION` is defined before the header is included.
+    SingleHeader,
+
+    /// A header and a `.c` file, like the amalgamation of SQLite. The header
+    /// has the declarations of the public headers. The `.c` file includes it
+    /// and has everything else.
+    Amalgamation,
+}
 
 #[derive(Debug, Clone)]
 pub struct Bundler {
+    pub mode: Mode,
     pub separators: bool,
+    pub line_markers: bool,
+
+    /// The file name that the `.c` file of an amalgamation includes its header
+    /// by.
+    pub header_name: String,
+
+    /// The revision the source files were read from, if they did not come
+    /// from the working tree. Recorded in the separators.
+    pub revision: Option<String>,
 }
 
 impl Bundler {
     pub fn bundle(&self, sources: &Sources) -> String {
         let mut out = String::new();
 
+        match self.mode {
+            Mode::Source => sources
+                .dependency_order()
+                .try_for_each(|source| self.write_source(&mut out, source)),
+            Mode::SingleHeader => self.write_single_header(&mut out, sources),
+            Mode::Amalgamation => self.write_amalgamation_source(&mut out, sources),
+        }
+        .expect("writing to String should never fail");
+
+        out
+    }
+
+    /// Make the header of an amalgamation. Other kinds of bundles don't have
+    /// one.
+    pub fn bundle_header(&self, sources: &Sources) -> Option<String> {
+        if self.mode != Mode::Amalgamation {
+            return None;
+        }
+
+        let mut out = String::new();
+
+        self.write_amalgamation_header(&mut out, sources)
+            .expect("writing to String should never fail");
+
+        Some(out)
+    }
+
+    /// Write the public source files inside an include guard. The guard is
+    /// named after [`Bundler::header_name`], with a `CBUNDL_` prefix so that
+    /// it does not collide with the guard of a public header of the same name.
+    fn write_amalgamation_header(&self, out: &mut String, sources: &Sources) -> fmt::Result {
+        let stem = Path::new(&self.header_name).file_stem().unwrap_or_default();
+
+        let name = macro_name(&stem.to_string_lossy());
+        let guard = format!("CBUNDL_{name}_AMALGAMATION_H");
+
+        writeln!(out, "#ifndef {guard}")?;
+        writeln!(out, "#define {guard}")?;
+        writeln!(out)?;
+
+        for source in sources.dependency_order().filter(|x| x.public) {
+            self.write_source(out, source)?;
+        }
+
+        self.write_end(out, "declarations")?;
+        writeln!(out, "#endif /* {guard} */")?;
+
+        Ok(())
+    }
+
+    /// Write every source file that is not public, after an include of the
+    /// header.
+    fn write_amalgamation_source(&self, out: &mut String, sources: &Sources) -> fmt::Result {
+        writeln!(out, "#include \"{}\"", self.header_name)?;
+        writeln!(out)?;
+
         sources
             .dependency_order()
-            .try_for_each(|source| -> Result<()> {
-                let file_name = source
-                    .path
-                    .file_name()
-                    .expect("source file paths should always have a last component");
-
-                if self.separators {
-                    let header = format!("bundled from \"{}\"", file_name.to_string_lossy());
-
-                    writeln!(out, "/**")?;
-                    writeln!(out, " * {}", header)?;
-                    writeln!(out, " */")?;
-                    writeln!(out)?;
-                }
+            .filter(|x| !x.public)
+            .try_for_each(|source| self.write_source(out, source))
+    }
 
-                out.write_str(&source.content)?;
-                if !source.content.ends_with("\n\n") {
-                    writeln!(out)?;
-                }
+    /// Write the declarations inside an include guard followed by the
+    /// implementation, which is only compiled if `<NAME>_IMPLEMENTATION` is
+    /// defined. `NAME` comes from the file name of the entry.
+    ///
+    /// The guards are prefixed with `CBUNDL_`, because the entry most likely
+    /// has its own guard named after its file, which is inside the bundle.
+    fn write_single_header(&self, out: &mut String, sources: &Sources) -> fmt::Result {
+        let stem = sources
+            .entry()
+            .path
+            .file_stem()
+            .expect("source file paths should always have a last component");
 
-                Ok(())
-            })
-            .expect("writing to String should never fail");
+        let name = macro_name(&stem.to_string_lossy());
+        let guard = format!("CBUNDL_{name}_SINGLE_HEADER_H");
+        let implementation = format!("{name}_IMPLEMENTATION");
+        let implementation_guard = format!("CBUNDL_{name}_SINGLE_HEADER_IMPLEMENTATION");
+
+        let (declarations, implementations): (Vec<_>, Vec<_>) = sources
+            .dependency_order()
+            .partition(|x| x.kind == SourceKind::Declaration);
+
+        writeln!(out, "#ifndef {guard}")?;
+        writeln!(out, "#define {guard}")?;
+        writeln!(out)?;
+
+        for source in declarations {
+            self.write_source(out, source)?;
+        }
+
+        self.write_end(out, "declarations")?;
+        writeln!(out, "#endif /* {guard} */")?;
+        writeln!(out)?;
+
+        // The implementation has its own guard, so that the header can be
+        // included more than once where the implementation is compiled.
+        writeln!(
+            out,
+            "#if defined({implementation}) && !defined({implementation_guard})"
+        )?;
+        writeln!(out, "#define {implementation_guard}")?;
+        writeln!(out)?;
+
+        for source in implementations {
+            self.write_source(out, source)?;
+        }
+
+        self.write_end(out, "implementation")?;
+        writeln!(out, "#endif /* {implementation} */")?;
+
+        Ok(())
+    }
+
+    fn write_source(&self, out: &mut String, source: &Source) -> fmt::Result {
+        let file_name = source
+            .path
+            .file_name()
+            .expect("source file paths should always have a last component");
+
+        let padded = !source.content.ends_with("\n\n");
+
+        if self.separators {
+            let mut header = format!("bundled from \"{}\"", file_name.to_string_lossy());
+
+            if let Some(revision) = self.revision.as_ref() {
+                header.push_str(" at ");
+                header.push_str(revision);
+            }
+
+            writeln!(out, "/**")?;
+            writeln!(out, " * {}", header)?;
+            writeln!(out, " *")?;
+            self.write_source_info(out, source, padded)?;
+            writeln!(out, " */")?;
+            writeln!(out)?;
+        }
+
+        if self.line_markers {
+            write_with_line_markers(out, source)?;
+        } else {
+            out.write_str(&source.content)?;
+        }
+
+        if padded {
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a separator that marks the end of the source files of a section,
+    /// so that the code generated after it is not mistaken for part of the
+    /// last source file.
+    fn write_end(&self, out: &mut String, section: &str) -> fmt::Result {
+        if self.separators {
+            writeln!(out, "/**")?;
+            writeln!(out, " * {END_KEY} {section}")?;
+            writeln!(out, " */")?;
+        }
+
+        Ok(())
+    }
+
+    /// Write everything needed to get back the original source file from the
+    /// bundle. See [`crate::unbundle`] for the other side.
+    fn write_source_info(&self, out: &mut String, source: &Source, padded: bool) -> fmt::Result {
+        let text = &source.original.text;
+
+        let kind = match source.kind {
+            SourceKind::Declaration => "declaration",
+            SourceKind::Implementation => "implementation",
+        };
+
+        write!(out, " * {SOURCE_KEY} path=")?;
+        write_quoted(out, &source.relative_path.to_string_lossy())?;
+        writeln!(
+            out,
+            " kind={kind} eol={eol} final-newline={final_newline} padded={padded} line-markers={line_markers}",
+            eol = if text.contains("\r\n") { "crlf" } else { "lf" },
+            final_newline = yes_no(text.is_empty() || text.ends_with('\n')),
+            padded = yes_no(padded),
+            line_markers = yes_no(self.line_markers),
+        )?;
+
+        let mut kept = source.line_numbers.iter().peekable();
+        for (line_no, line) in (1..).zip(text.lines()) {
+            if kept.next_if_eq(&&line_no).is_some() {
+                continue;
+            }
+
+            write!(out, " * {REMOVED_KEY} line={line_no} text=")?;
+            write_quoted(out, line)?;
+            writeln!(out)?;
+        }
+
+        for (old, new) in &source.renamed {
+            write!(out, " * {RENAMED_KEY} from=")?;
+            write_quoted(out, old)?;
+            write!(out, " to=")?;
+            write_quoted(out, new)?;
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub const SOURCE_KEY: &str = "cbundl-source:";
+pub const REMOVED_KEY: &str = "cbundl-removed:";
+pub const RENAMED_KEY: &str = "cbundl-renamed:";
+pub const END_KEY: &str = "cbundl-end:";
+
+/// Turn `s` into an identifier that is suitable for a macro.
+fn macro_name(s: &str) -> String {
+    let mut out: String = s
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        out.insert(0, '_');
+    }
+
+    out
+}
+
+fn yes_no(x: bool) -> &'static str {
+    if x {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Write `s` as a quoted string that can safely be placed inside a block comment.
+fn write_quoted(out: &mut String, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+
+    let mut iter = s.chars().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' | '"' => {
+                out.write_char('\\')?;
+                out.write_char(c)?;
+            }
+            // Don't let `*/` end the comment.
+            '*' if iter.peek() == Some(&'/') => {
+                out.write_str("*\\")?;
+            }
+            _ => out.write_char(c)?,
+        }
+    }
+
+    out.write_char('"')
+}
+
+/// Write the contents of `source` and emit a `#line` marker at the start and
+/// wherever lines were removed. An end marker after the contents keeps the
+/// lines written after them from being mapped to the source file.
+///
+/// Markers use the relative path of the source file, so that the bundle does
+/// not depend on where the source files are.
+fn write_with_line_markers(out: &mut String, source: &Source) -> fmt::Result {
+    let mut expected = None;
+
+    for (line, line_no) in source.content.lines().zip(&source.line_numbers) {
+        if expected != Some(*line_no) {
+            write_marker(out, *line_no, &source.relative_path)?;
+        }
+
+        writeln!(out, "{line}")?;
+        expected = Some(line_no + 1);
+    }
+
+    write_end_marker(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+    use crate::BundleOptions;
+
+    /// A library whose public header has the usual guard named after its file.
+    fn library() -> MemoryFs {
+        let mut fs = MemoryFs::new();
+        fs.insert(
+            "/lib/mylib.h",
+            "#ifndef MYLIB_H\n#define MYLIB_H\n// cbundl: impl=mylib.c\nint mylib_add(int a, int b);\n#endif\n",
+        );
+        fs.insert(
+            "/lib/mylib.c",
+            "// cbundl: bundle\n#include \"mylib.h\"\nint mylib_add(int a, int b) { return a + b; }\n",
+        );
+        fs
+    }
+
+    fn options(mode: Mode) -> BundleOptions {
+        BundleOptions::new("/lib/mylib.h")
+            .mode(mode)
+            .banner(false)
+            .format(false)
+    }
+
+    /// Get the lines of `code` that a compiler would see with the macros in
+    /// `defined`. Only understands what the bundler itself generates.
+    fn compiled(code: &str, defined: &[&str]) -> String {
+        let mut defined: HashSet<String> = defined.iter().map(|x| x.to_string()).collect();
+        let mut active = vec![true];
+        let mut out = String::new();
+
+        for line in code.lines() {
+            let on = active.iter().all(|x| *x);
+
+            if let Some(x) = line.strip_prefix("#ifndef ") {
+                active.push(!defined.contains(x.trim()));
+            } else if let Some(x) = line.strip_prefix("#if ") {
+                let value = x.split("&&").all(|term| {
+                    let term = term.trim();
+                    let (negated, term) = match term.strip_prefix('!') {
+                        Some(x) => (true, x),
+                        None => (false, term),
+                    };
+                    let name = term.trim_start_matches("defined(").trim_end_matches(')');
+                    defined.contains(name) != negated
+                });
+                active.push(value);
+            } else if line.starts_with("#endif") {
+                active.pop();
+            } else if let Some(x) = line.strip_prefix("#define ") {
+                if on {
+                    defined.insert(x.trim().to_owned());
+                }
+            } else if on {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
 
         out
     }
+
+    #[test]
+    fn single_header_guard_does_not_hide_the_entry() {
+        let bundle = options(Mode::SingleHeader).bundle_in(&library()).unwrap();
+
+        let user = compiled(&bundle.code, &[]);
+        assert!(
+            user.contains("int mylib_add(int a, int b);"),
+            "{}",
+            bundle.code
+        );
+        assert!(!user.contains("return a + b;"));
+
+        let implementation = compiled(&bundle.code, &["MYLIB_IMPLEMENTATION"]);
+        assert!(implementation.contains("int mylib_add(int a, int b);"));
+        assert!(implementation.contains("return a + b;"));
+    }
+
+    #[test]
+    fn amalgamation_guard_does_not_hide_the_public_header() {
+        let bundle = options(Mode::Amalgamation)
+            .header_name("mylib.h")
+            .bundle_in(&library())
+            .unwrap();
+
+        let header = bundle.public_header.expect("amalgamations have a header");
+        let user = compiled(&header.code, &[]);
+        assert!(
+            user.contains("int mylib_add(int a, int b);"),
+            "{}",
+            header.code
+        );
+
+        assert!(bundle.code.starts_with("#include \"mylib.h\""));
+        assert!(bundle.code.contains("return a + b;"));
+    }
+
+    #[test]
+    fn macro_names() {
+        assert_eq!(macro_name("mylib"), "MYLIB");
+        assert_eq!(macro_name("my-lib.v2"), "MY_LIB_V2");
+        assert_eq!(macro_name("2d"), "_2D");
+    }
 }
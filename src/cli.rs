@@ -1,57 +1,153 @@
-use std::fs::File;
-use std::io::{stdout, Write};
-use std::path::PathBuf;
-
-use eyre::{Context, Result};
-
-use crate::banner::Banner;
-use crate::bundler::Bundler;
-use crate::config::Config;
-use crate::display::display_path;
-use crate::formatter::Formatter;
-use crate::header::Header;
-use crate::pipeline::Pipeline;
-use crate::quotes::Quotes;
-use crate::source::Sources;
+use std::fs::{self, File};
+use std::io::{self, stdout, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use eyre::{bail, eyre, Context, Result};
+
+use cbundl::display::display_path;
+use cbundl::graph::{self, GraphFormat};
+use cbundl::line_markers::{self, Location};
+use cbundl::unbundle;
+use cbundl::vfs::{FileSystem, RealFs};
+use cbundl::{
+    Bundle, BundleOptions, Depfile, Diagnostics, Error, Mode, PublicHeader, SourceMap, Sources,
+    DIAGNOSTIC_LOG_TARGET,
+};
+
+use crate::config::{self, Command, Config, ConfigCommand};
+use crate::settings::{self, SettingsFormat};
+use crate::watch;
 
 pub fn run() -> Result<()> {
-    let config = Config::new()?;
+    let args = config::parse_args();
+
+    match Command::from_args(&args) {
+        Some(Command::Map { location }) => map(&location),
+        Some(Command::Graph { format, entry }) => graph(&args, entry, format),
+        Some(Command::Unbundle {
+            bundle,
+            output,
+            force,
+        }) => unbundle(&bundle, &output, force),
+        Some(Command::Config {
+            command: ConfigCommand::Show { format, entry },
+        }) => config_show(&args, entry, format),
+        Some(Command::Config {
+            command: ConfigCommand::Check,
+        }) => config_check(&args),
+        None => bundle(&args),
+    }
+}
+
+fn bundle(args: &ArgMatches) -> Result<()> {
+    let configs = Config::all(args)?;
+
+    if !configs.iter().any(|x| x.watch) {
+        return build_all(configs).map(|_| ());
+    }
+
+    if configs.iter().any(|x| x.output_file.is_none()) {
+        bail!("watch mode needs the bundle to be written to a file");
+    }
+
+    if configs.iter().any(|x| x.git_rev.is_some()) {
+        bail!("watch mode cannot be used with a git revision");
+    }
+
+    let mut roots: Vec<PathBuf> = configs.iter().map(|x| x.entry.clone()).collect();
+    roots.extend(configs[0].config_file.clone());
+
+    // Reload the configuration before every rebuild so that changes to it
+    // are picked up too.
+    let mut configs = Some(configs);
+    watch::watch(&roots, || {
+        let configs = match configs.take() {
+            Some(x) => x,
+            None => Config::all(args)?,
+        };
+
+        build_all(configs)
+    })
+}
+
+/// Make every bundle in `configs`. A bundle that fails does not stop the
+/// others from being made. Returns every file that was used to make them.
+fn build_all(configs: Vec<Config>) -> Result<Vec<PathBuf>> {
+    let n = configs.len();
+
+    let mut dependencies = Vec::new();
+    let mut failed = 0;
+
+    for config in configs {
+        let target = config.target.clone();
+
+        let e = match build(config) {
+            Ok(x) => {
+                dependencies.extend(x);
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        let e = match target {
+            Some(x) => e.wrap_err(format!("failed to build target `{x}`")),
+            None => e,
+        };
+
+        if n == 1 {
+            return Err(e);
+        }
+
+        crate::log_error(&e);
+        failed += 1;
+    }
+
+    if failed != 0 {
+        bail!("failed to build {failed} of {n} targets");
+    }
+
+    Ok(dependencies)
+}
+
+/// Make the bundle described by `config`. Returns every file that was used to
+/// make it.
+fn build(config: Config) -> Result<Vec<PathBuf>> {
     trace!("config = {config:#?}");
 
-    let sources = Sources::new(config.entry)?;
+    if config.depfile.is_some() && config.output_file.is_none() {
+        bail!("a depfile can only be written if the bundle is written to a file");
+    }
 
-    let bundler = Bundler {
-        separators: config.bundle_separators,
-    };
+    if config.depfile.is_some() && config.git_rev.is_some() {
+        bail!("a depfile cannot be written for a git revision");
+    }
 
-    let header = config.header_source.map(|source| Header { source });
-
-    let quotes = config.enable_quote.then_some(Quotes {
-        deterministic: config.deterministic,
-        picker: config.quote_picker,
-        custom_quotes: config.custom_quotes,
-    });
-
-    let banner = (!config.no_banner).then_some(Banner {
-        deterministic: config.deterministic,
-        quotes,
-    });
-
-    let formatter = (!config.no_format).then_some(Formatter {
-        exe: config.formatter,
-        args: config.formatter_args,
-    });
-
-    let mut pipeline = Pipeline {
-        bundler,
-        header,
-        banner,
-        formatter,
-    };
+    let header_path = public_header_path(config.mode, config.output_file.as_deref())?;
+
+    let Bundle {
+        code,
+        source_map,
+        public_header,
+        mut dependencies,
+    } = bundle_options(&config)
+        .bundle_in(file_system(&config))
+        .map_err(report_error)?;
 
-    let bundle = pipeline.process(&sources)?;
+    if let (Some(path), Some(header)) = (header_path.as_ref(), public_header) {
+        write_public_header(&header, path, &dependencies)?;
+    }
+
+    // The paths of the source files are canonical, so the configuration file
+    // has to be too for it to be listed only once.
+    if let Some(path) = config.config_file.as_ref() {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        if !dependencies.contains(&path) {
+            dependencies.push(path);
+        }
+    }
 
-    write_bundle(bundle, config.output_file.as_ref()).with_context(|| {
+    write_bundle(code, config.output_file.as_ref()).with_context(|| {
         if let Some(path) = config.output_file.as_ref() {
             format!("failed to write bundle to `{}`", display_path(path))
         } else {
@@ -59,9 +155,233 @@ pub fn run() -> Result<()> {
         }
     })?;
 
+    if let Some(source_map) = source_map {
+        match config.output_file.as_ref() {
+            Some(path) => write_source_map(&source_map, path)?,
+            None => warn!("not writing source map because the bundle was written to stdout"),
+        }
+    }
+
+    if let (Some(path), Some(target)) = (config.depfile.as_ref(), config.output_file) {
+        let depfile = Depfile {
+            target,
+            dependencies: dependencies.clone(),
+        };
+
+        fs::write(path, depfile.render())
+            .with_context(|| format!("failed to write depfile to `{}`", display_path(path)))?;
+    }
+
+    Ok(dependencies)
+}
+
+fn bundle_options(config: &Config) -> BundleOptions {
+    let mut options = BundleOptions::new(&config.entry)
+        .keep_going(config.keep_going)
+        .mode(config.mode)
+        .rename_statics(config.rename_statics)
+        .separators(config.bundle_separators)
+        .line_markers(config.line_markers)
+        .source_map(config.source_map)
+        .deterministic(config.deterministic)
+        .revision(config.git_rev.as_ref().map(|x| x.describe()))
+        .header(config.header_source.clone())
+        .banner(!config.no_banner)
+        .quotes(config.enable_quote)
+        .quote_picker(config.quote_picker.clone())
+        .custom_quotes(config.custom_quotes.clone())
+        .format(!config.no_format)
+        .formatter(&config.formatter)
+        .formatter_args(config.formatter_args.clone());
+
+    for x in &config.include_paths {
+        options = options.include_path(x);
+    }
+
+    for x in &config.system_include_paths {
+        options = options.system_include_path(x);
+    }
+
+    for x in &config.public_headers {
+        options = options.public_header(x);
+    }
+
+    if let Ok(Some(path)) = public_header_path(config.mode, config.output_file.as_deref()) {
+        if let Some(name) = path.file_name() {
+            options = options.header_name(name.to_string_lossy());
+        }
+    }
+
+    options
+}
+
+/// Get where the header of an amalgamation goes. It is written next to the
+/// bundle at `output` with a `.h` extension.
+fn public_header_path(mode: Mode, output: Option<&Path>) -> Result<Option<PathBuf>> {
+    if mode != Mode::Amalgamation {
+        return Ok(None);
+    }
+
+    let Some(output) = output else {
+        bail!("an amalgamation needs the bundle to be written to a file");
+    };
+
+    let path = output.with_extension("h");
+    if path == output {
+        bail!(
+            "the bundle of an amalgamation cannot be written to `{}` because its header goes there",
+            display_path(output)
+        );
+    }
+
+    Ok(Some(path))
+}
+
+/// Write the header of an amalgamation to `path`, unless `path` is one of the
+/// files the bundle was made from.
+fn write_public_header(header: &PublicHeader, path: &Path, dependencies: &[PathBuf]) -> Result<()> {
+    if let Ok(real_path) = fs::canonicalize(path) {
+        if dependencies
+            .iter()
+            .any(|x| fs::canonicalize(x).is_ok_and(|x| x == real_path))
+        {
+            bail!(
+                "refusing to overwrite `{}` with the header of the amalgamation because it is one of its source files",
+                display_path(path)
+            );
+        }
+    }
+
+    fs::write(path, &header.code)
+        .with_context(|| format!("failed to write header to `{}`", display_path(path)))?;
+
+    if let Some(source_map) = header.source_map.as_ref() {
+        write_source_map(source_map, path)?;
+    }
+
+    Ok(())
+}
+
+/// Get the filesystem the source files should be read from.
+fn file_system(config: &Config) -> &dyn FileSystem {
+    match config.git_rev.as_ref() {
+        Some(x) => x,
+        None => &RealFs,
+    }
+}
+
+fn load_sources(config: &Config) -> Result<Sources> {
+    bundle_options(config)
+        .sources_in(file_system(config))
+        .map_err(report_error)
+}
+
+fn graph(args: &ArgMatches, entry: PathBuf, format: GraphFormat) -> Result<()> {
+    let config = Config::new(args, Some(entry))?;
+    trace!("config = {config:#?}");
+
+    let sources = load_sources(&config)?;
+
+    stdout()
+        .write_all(graph::render(&sources, format).as_bytes())
+        .context("failed to write graph to stdout")
+}
+
+fn config_show(args: &ArgMatches, entry: Option<PathBuf>, format: SettingsFormat) -> Result<()> {
+    let config = Config::new(args, entry)?;
+
+    stdout()
+        .write_all(settings::render(&config.settings, format)?.as_bytes())
+        .context("failed to write settings to stdout")
+}
+
+fn config_check(args: &ArgMatches) -> Result<()> {
+    let path = Config::check(args)?;
+    info!("`{}` is valid", display_path(&path));
+    Ok(())
+}
+
+fn write_source_map(source_map: &SourceMap, bundle: &Path) -> Result<()> {
+    let path = SourceMap::sidecar_path(bundle);
+
+    let json =
+        serde_json::to_string_pretty(source_map).context("failed to serialize source map")?;
+
+    fs::write(&path, json)
+        .with_context(|| format!("failed to write source map to `{}`", display_path(&path)))
+}
+
+fn map(location: &str) -> Result<()> {
+    let (bundle, line) = location
+        .rsplit_once(':')
+        .and_then(|(bundle, line)| Some((Path::new(bundle), line.parse::<usize>().ok()?)))
+        .ok_or_else(|| eyre!("invalid location `{location}` (expected: `<bundle>:<line>`)"))?;
+
+    let source_map_path = SourceMap::sidecar_path(bundle);
+
+    let found = match fs::read_to_string(&source_map_path) {
+        Ok(x) => {
+            let source_map: SourceMap = serde_json::from_str(&x).with_context(|| {
+                format!(
+                    "failed to parse source map `{}`",
+                    display_path(&source_map_path)
+                )
+            })?;
+
+            source_map.lookup(line)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let code = fs::read_to_string(bundle)
+                .with_context(|| format!("failed to read bundle `{}`", display_path(bundle)))?;
+
+            line_markers::locations(&code)
+                .get(line.wrapping_sub(1))
+                .cloned()
+                .flatten()
+        }
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!(
+                    "failed to read source map `{}`",
+                    display_path(&source_map_path)
+                )
+            })
+        }
+    };
+
+    let Location { path, line } = found.ok_or_else(|| {
+        eyre!(
+            "line {line} of `{}` does not map to any source file",
+            display_path(bundle)
+        )
+    })?;
+
+    println!("{path}:{line}");
     Ok(())
 }
 
+fn report_error(e: Error) -> eyre::Report {
+    match e {
+        Error::Sources(x) => report_diagnostics(x),
+        e => e.into(),
+    }
+}
+
+/// Turn `diagnostics` into a single error. If there is more than one, all of
+/// them are logged and the returned error only summarizes them.
+fn report_diagnostics(diagnostics: Diagnostics) -> eyre::Report {
+    let n = diagnostics.len();
+    if n == 1 {
+        return eyre::Report::new(diagnostics);
+    }
+
+    for diagnostic in diagnostics {
+        error!(target: DIAGNOSTIC_LOG_TARGET, "{diagnostic}\n");
+    }
+
+    eyre!("aborting due to {n} previous errors")
+}
+
 fn write_bundle(bundle: String, path: Option<&PathBuf>) -> Result<()> {
     let mut writer: Box<dyn Write> = if let Some(path) = path {
         Box::new(
@@ -79,3 +399,37 @@ fn write_bundle(bundle: String, path: Option<&PathBuf>) -> Result<()> {
     writer.flush()?;
     Ok(())
 }
+
+fn unbundle(bundle: &Path, output: &Path, force: bool) -> Result<()> {
+    let code = fs::read_to_string(bundle)
+        .with_context(|| format!("failed to read bundle `{}`", display_path(bundle)))?;
+
+    let files = unbundle::unbundle(&code)
+        .with_context(|| format!("failed to unbundle `{}`", display_path(bundle)))?;
+
+    unbundle::write_files(&files, output, force)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amalgamation_header_goes_next_to_the_bundle() {
+        let path = |mode, output: Option<&str>| public_header_path(mode, output.map(Path::new));
+
+        assert_eq!(
+            path(Mode::Amalgamation, Some("dist/mylib.c")).unwrap(),
+            Some(PathBuf::from("dist/mylib.h"))
+        );
+        assert_eq!(path(Mode::Source, Some("mylib.h")).unwrap(), None);
+        assert!(path(Mode::Amalgamation, None).is_err());
+
+        let e = path(Mode::Amalgamation, Some("foo.h")).unwrap_err();
+        assert!(
+            e.to_string().contains("because its header goes there"),
+            "{e}"
+        );
+    }
+}
@@ -1,19 +1,26 @@
-use std::fs;
+use std::collections::HashMap;
 use std::io;
-use std::path::{Path, PathBuf};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
 
+use clap::error::ErrorKind;
 use clap::parser::ValueSource;
-use clap::{ArgMatches, CommandFactory, Parser, ValueEnum};
-use eyre::bail;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use eyre::{bail, eyre};
 use eyre::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::consts::{
+use cbundl::consts::{
     CRATE_DESCRIPTION, DEFAULT_CONFIG_FILES, DEFAULT_FORMATTER, LONG_VERSION, SHORT_VERSION,
 };
-use crate::display::display_path;
-use crate::header::HeaderSource;
-use crate::quotes::{CustomQuote, QuotePicker};
+use cbundl::display::display_path;
+use cbundl::graph::GraphFormat;
+use cbundl::vfs::{FileSystem, GitFs, RealFs};
+use cbundl::{CustomQuote, QuotePicker, SourceText};
+use cbundl::{HeaderSource, Mode};
+
+use crate::settings::{Origin, Setting, SettingsFormat};
+use crate::strict::{self, Segment};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 enum BooleanFlag {
@@ -32,12 +39,15 @@ impl From<BooleanFlag> for bool {
     version = SHORT_VERSION,
     long_version = LONG_VERSION,
     about = CRATE_DESCRIPTION,
-    long_about = None
+    long_about = None,
+    override_usage = "cbundl [OPTIONS] [path]\n       cbundl [OPTIONS] <COMMAND>",
+    subcommand_negates_reqs = true
 )]
 struct Args {
     #[arg(
         long,
-        help = "Don't load any configuration file. (Overrides `--config`)"
+        help = "Don't load any configuration file. (Overrides `--config`)",
+        global = true
     )]
     no_config: bool,
 
@@ -45,10 +55,31 @@ struct Args {
         long,
         help = "Specify an alternate configuration file.",
         value_name = "path",
-        default_values = DEFAULT_CONFIG_FILES
+        default_values = DEFAULT_CONFIG_FILES,
+        global = true
     )]
     config: Option<PathBuf>,
 
+    #[arg(
+        long,
+        help = "Use the settings of a profile from the configuration file.",
+        long_help = "Use the settings of a profile from the configuration file. They take priority over the settings of targets and the rest of the file.",
+        value_name = "name",
+        env = "CBUNDL_PROFILE",
+        global = true
+    )]
+    profile: Option<String>,
+
+    #[arg(
+        long,
+        help = "What kind of bundle to make.",
+        long_help = "What kind of bundle to make. `source` makes one `.c` file. `single-header` makes a header-only library out of a public header: the declarations go inside an include guard and the implementation is only compiled where `<NAME>_IMPLEMENTATION` is defined. `amalgamation` makes a header with the public headers of a library and a `.c` file with everything else. The header is written next to the output with a `.h` extension.",
+        value_name = "mode",
+        default_value = "source",
+        global = true
+    )]
+    mode: Mode,
+
     #[arg(
         long = "deterministic",
         help = "Output a deterministic bundle.",
@@ -58,18 +89,99 @@ struct Args {
         require_equals = true,
         default_missing_value = "yes",
         hide_default_value = true,
+        global = true,
     )]
     deterministic: BooleanFlag,
 
+    #[arg(
+        long,
+        help = "Emit `#line` markers so compiler errors point at the original files.",
+        default_value = "no",
+        value_name = "boolean",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "yes",
+        hide_default_value = true,
+        global = true,
+    )]
+    line_markers: BooleanFlag,
+
+    #[arg(
+        long,
+        help = "Report every error in the source files instead of stopping at the first one.",
+        default_value = "no",
+        value_name = "boolean",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "yes",
+        hide_default_value = true,
+        global = true,
+    )]
+    keep_going: BooleanFlag,
+
+    #[arg(
+        long,
+        help = "Rename `static` symbols that more than one `.c` file defines.",
+        long_help = "Rename `static` symbols that more than one `.c` file defines, instead of failing. Each of them is prefixed with the path of its file, like `src_util_c_count`.",
+        default_value = "no",
+        value_name = "boolean",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "yes",
+        hide_default_value = true,
+        global = true,
+    )]
+    rename_statics: BooleanFlag,
+
+    #[arg(
+        short = 'I',
+        long = "include-path",
+        help = "Add a directory to search for bundled includes.",
+        long_help = "Add a directory to search for bundled includes. Directories are searched in order after the directory of the including file. Can be given multiple times.",
+        value_name = "dir",
+        action = ArgAction::Append,
+        global = true
+    )]
+    include_paths: Vec<PathBuf>,
+
+    #[arg(
+        long = "public-header",
+        help = "Add a public header to an amalgamation.",
+        long_help = "Add a public header to an amalgamation. Its declarations and the ones of everything it includes go in the header of the amalgamation. Defaults to the entry. Can be given multiple times.",
+        value_name = "path",
+        action = ArgAction::Append,
+        global = true
+    )]
+    public_headers: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Bundle the files as they are in a git revision.",
+        long_help = "Bundle the files as they are in a git revision, like a tag or a commit, without checking it out. The source files, the configuration file and the header file are read with `git show <git-rev>:<path>`. The working tree is left alone.",
+        value_name = "git-rev",
+        global = true
+    )]
+    rev: Option<String>,
+
     #[arg(
         short = 'o',
         long = "output",
         help = "Specify where to write the resulting bundle.",
         value_name = "path",
-        default_value = "-"
+        default_value = "-",
+        global = true
     )]
     output_file: PathBuf,
 
+    #[arg(
+        long,
+        help = "Write a Makefile-style depfile for the bundle.",
+        long_help = "Write a Makefile-style depfile for the bundle, like `cc -MD`. It lists every source file of the bundle, the configuration file and the header file, so build systems know when to rebuild the bundle.",
+        value_name = "path",
+        global = true
+    )]
+    depfile: Option<PathBuf>,
+
     #[arg(
         long,
         help = "Don't output the banner at the top of the bundle.",
@@ -79,6 +191,7 @@ struct Args {
         require_equals = true,
         default_missing_value = "yes",
         hide_default_value = true,
+        global = true,
     )]
     no_banner: BooleanFlag,
 
@@ -91,6 +204,7 @@ struct Args {
         require_equals = true,
         default_missing_value = "yes",
         hide_default_value = true,
+        global = true,
     )]
     no_format: BooleanFlag,
 
@@ -99,16 +213,168 @@ struct Args {
         help = "Code formatter executable.",
         long_help = "Code formatter. Must format the code from stdin and write it to stdout.",
         value_name = "exe",
-        default_value = DEFAULT_FORMATTER
+        default_value = DEFAULT_FORMATTER,
+        global = true
     )]
     formatter: PathBuf,
 
-    #[arg(help = "Path to the entry source file.", value_name = "path")]
-    entry: PathBuf,
+    #[arg(
+        long,
+        help = "Write a source map next to the bundle.",
+        long_help = "Write a source map next to the bundle. The source map is a JSON file named after the bundle with a `.map.json` suffix, which maps each line range of the bundle to its original file and line.",
+        default_value = "no",
+        value_name = "boolean",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "yes",
+        hide_default_value = true,
+        global = true,
+    )]
+    source_map: BooleanFlag,
+
+    #[arg(
+        long,
+        help = "Bundle again whenever one of the files of the bundle changes.",
+        default_value = "no",
+        value_name = "boolean",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "yes",
+        hide_default_value = true,
+        global = true,
+    )]
+    watch: BooleanFlag,
+
+    #[arg(
+        long,
+        help = "Build only this target of the configuration file.",
+        value_name = "name",
+        conflicts_with = "entry"
+    )]
+    target: Option<String>,
+
+    #[arg(
+        help = "Path to the entry source file.",
+        long_help = "Path to the entry source file. If not given, every target of the configuration file is built.",
+        value_name = "path"
+    )]
+    entry: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    #[command(about = "Find out which file and line a line of a bundle came from.")]
+    Map {
+        #[arg(
+            help = "Line of the bundle to look up.",
+            long_help = "Line of the bundle to look up. The source map next to the bundle is used if it exists, otherwise the line markers inside the bundle.",
+            value_name = "bundle:line"
+        )]
+        location: String,
+    },
+
+    #[command(about = "Print the graph of source files.")]
+    Graph {
+        #[arg(
+            long,
+            help = "Format of the graph.",
+            value_name = "format",
+            default_value = "dot"
+        )]
+        format: GraphFormat,
+
+        #[arg(help = "Path to the entry source file.", value_name = "path")]
+        entry: PathBuf,
+    },
+
+    #[command(about = "Split a bundle back into the files it was made from.")]
+    Unbundle {
+        #[arg(
+            help = "Path to the bundle.",
+            long_help = "Path to the bundle. It must have been made with separators enabled.",
+            value_name = "path"
+        )]
+        bundle: PathBuf,
+
+        // Same id as the global `--output`, so that this one takes its place
+        // instead of clashing with it.
+        #[arg(
+            id = "output_file",
+            short = 'o',
+            long = "output",
+            help = "Directory to write the files to.",
+            value_name = "dir",
+            default_value = "."
+        )]
+        output: PathBuf,
+
+        #[arg(long = "force", help = "Overwrite files that already exist.")]
+        force: bool,
+    },
+
+    #[command(about = "Inspect the configuration.")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    #[command(about = "Print every setting and where its value came from.")]
+    Show {
+        #[arg(
+            long,
+            help = "Format of the settings.",
+            value_name = "format",
+            default_value = "toml"
+        )]
+        format: SettingsFormat,
+
+        #[arg(help = "Path to the entry source file.", value_name = "path")]
+        entry: Option<PathBuf>,
+    },
+
+    #[command(about = "Check the configuration file for errors.")]
+    Check,
+}
+
+impl Command {
+    pub fn from_args(args: &ArgMatches) -> Option<Self> {
+        args.subcommand_name()?;
+        let x = Self::from_arg_matches(args).expect("subcommand arguments should be validated");
+        Some(x)
+    }
+}
+
+pub fn parse_args() -> ArgMatches {
+    let mut command = Args::command();
+    let args = command.get_matches_mut();
+
+    // The options are settings that the subcommands use too, so only the
+    // entry cannot be given together with a subcommand.
+    if args.subcommand_name().is_some() && args.value_source("entry").is_some() {
+        command
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the argument '[path]' cannot be used with subcommands",
+            )
+            .exit();
+    }
+
+    args
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct File {
+    /// The file as it was written, to find out which section a setting
+    /// came from.
+    #[serde(skip)]
+    raw: toml::Table,
+
     bundle: Option<BundleSection>,
     header: Option<HeaderSection>,
     banner: Option<BannerSection>,
@@ -116,12 +382,48 @@ struct File {
 
     #[serde(rename = "quote")]
     quotes: Option<Vec<CustomQuote>>,
+
+    #[serde(rename = "target", default)]
+    targets: Vec<TargetSection>,
+
+    #[serde(rename = "profile", default)]
+    profiles: HashMap<String, ProfileSection>,
+}
+
+/// Settings that only apply when the profile is selected. Its sections
+/// override the sections of the file.
+#[derive(Debug, Clone, Deserialize)]
+struct ProfileSection {
+    bundle: Option<BundleSection>,
+    header: Option<HeaderSection>,
+    banner: Option<BannerSection>,
+    formatter: Option<FormatterSection>,
 }
 
+/// A bundle to make. Its sections override the sections of the file.
 #[derive(Debug, Clone, Deserialize)]
+struct TargetSection {
+    name: String,
+    entry: PathBuf,
+    output: Option<PathBuf>,
+
+    bundle: Option<BundleSection>,
+    header: Option<HeaderSection>,
+    banner: Option<BannerSection>,
+    formatter: Option<FormatterSection>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 struct BundleSection {
+    mode: Option<Mode>,
     separators: Option<bool>,
+    line_markers: Option<bool>,
+    source_map: Option<bool>,
     deterministic: Option<bool>,
+    include_paths: Option<Vec<PathBuf>>,
+    system_include_paths: Option<Vec<PathBuf>>,
+    public_headers: Option<Vec<PathBuf>>,
+    rename_statics: Option<bool>,
 
     #[serde(rename = "output")]
     output_file: Option<PathBuf>,
@@ -155,26 +457,220 @@ struct FormatterSection {
     args: Option<Vec<String>>,
 }
 
+/// Configuration sections that can be layered on top of each other.
+trait Overlay {
+    /// Merge `top` into `self`. Values set in `top` win.
+    fn overlay(self, top: Self) -> Self;
+}
+
+impl<T: Overlay> Overlay for Option<T> {
+    fn overlay(self, top: Self) -> Self {
+        match (self, top) {
+            (Some(x), Some(top)) => Some(x.overlay(top)),
+            (x, top) => top.or(x),
+        }
+    }
+}
+
+impl Overlay for BundleSection {
+    fn overlay(self, top: Self) -> Self {
+        Self {
+            mode: top.mode.or(self.mode),
+            separators: top.separators.or(self.separators),
+            line_markers: top.line_markers.or(self.line_markers),
+            source_map: top.source_map.or(self.source_map),
+            deterministic: top.deterministic.or(self.deterministic),
+            include_paths: top.include_paths.or(self.include_paths),
+            system_include_paths: top.system_include_paths.or(self.system_include_paths),
+            public_headers: top.public_headers.or(self.public_headers),
+            rename_statics: top.rename_statics.or(self.rename_statics),
+            output_file: top.output_file.or(self.output_file),
+        }
+    }
+}
+
+impl Overlay for HeaderSection {
+    fn overlay(self, top: Self) -> Self {
+        // `text` and `source` are mutually exclusive, so setting one of them
+        // replaces both.
+        let (text, source) = if top.text.is_some() || top.source.is_some() {
+            (top.text, top.source)
+        } else {
+            (self.text, self.source)
+        };
+
+        Self {
+            enable: top.enable.or(self.enable),
+            text,
+            source,
+        }
+    }
+}
+
+impl Overlay for BannerSection {
+    fn overlay(self, top: Self) -> Self {
+        Self {
+            enable: top.enable.or(self.enable),
+            quote: self.quote.overlay(top.quote),
+        }
+    }
+}
+
+impl Overlay for QuoteSection {
+    fn overlay(self, top: Self) -> Self {
+        Self {
+            enable: top.enable.or(self.enable),
+            picker: top.picker.or(self.picker),
+        }
+    }
+}
+
+impl Overlay for FormatterSection {
+    fn overlay(self, top: Self) -> Self {
+        Self {
+            enable: top.enable.or(self.enable),
+            path: top.path.or(self.path),
+            args: top.args.or(self.args),
+        }
+    }
+}
+
+/// Get the keys that are valid in the table at `path` of a configuration file.
+fn known_keys(path: &[Segment]) -> &'static [&'static str] {
+    // Only the kind of table matters, not which target or profile it is in.
+    let mut keys: Vec<&str> = Vec::with_capacity(path.len());
+    let mut path = path.iter();
+    while let Some(segment) = path.next() {
+        match segment {
+            Segment::Key(x) if keys.is_empty() && x == "profile" => {
+                keys.push(x);
+                path.next();
+            }
+            Segment::Key(x) => keys.push(x),
+            Segment::Index(_) => {}
+        }
+    }
+
+    match keys.as_slice() {
+        [] => strict::fields::<File>(),
+        ["target"] => strict::fields::<TargetSection>(),
+        ["profile"] => strict::fields::<ProfileSection>(),
+        ["quote"] => strict::fields::<CustomQuote>(),
+        [.., "banner", "quote"] => strict::fields::<QuoteSection>(),
+        [.., "bundle"] => strict::fields::<BundleSection>(),
+        [.., "header"] => strict::fields::<HeaderSection>(),
+        [.., "banner"] => strict::fields::<BannerSection>(),
+        [.., "formatter"] => strict::fields::<FormatterSection>(),
+        _ => &[],
+    }
+}
+
 impl File {
-    fn read(path: &Path) -> Option<Result<Self>> {
-        let x = match fs::read_to_string(path) {
+    /// Apply the overrides of `profile`.
+    fn with_profile(self, profile: ProfileSection) -> Self {
+        Self {
+            bundle: self.bundle.overlay(profile.bundle),
+            header: self.header.overlay(profile.header),
+            banner: self.banner.overlay(profile.banner),
+            formatter: self.formatter.overlay(profile.formatter),
+            ..self
+        }
+    }
+
+    /// Apply the overrides of `target`.
+    fn with_target(self, target: TargetSection) -> Self {
+        let mut bundle = target.bundle;
+        if let Some(output) = target.output {
+            bundle
+                .get_or_insert_with(BundleSection::default)
+                .output_file = Some(output);
+        }
+
+        Self {
+            bundle: self.bundle.overlay(bundle),
+            header: self.header.overlay(target.header),
+            banner: self.banner.overlay(target.banner),
+            formatter: self.formatter.overlay(target.formatter),
+            ..self
+        }
+    }
+
+    fn read(fs: &dyn FileSystem, path: &Path) -> Option<Result<Self>> {
+        let x = match fs.read_to_string(path) {
             Ok(x) => x,
             Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
             Err(e) => return Some(Err(e).context("failed to read file")),
         };
 
-        let x = toml::from_str(&x).context("failed to parse file");
-        Some(x)
+        let source = SourceText::new(path.to_path_buf(), x);
+        let file = strict::from_str(&source, known_keys).and_then(|mut file: Self| {
+            file.raw = toml::from_str(&source.text).context("failed to parse file")?;
+            Ok(file)
+        });
+
+        Some(file)
+    }
+
+    /// The override of the profile called `name`.
+    fn profile_override(&self, name: &str) -> Override {
+        let table = self
+            .raw
+            .get("profile")
+            .and_then(|x| x.get(name))
+            .and_then(|x| x.as_table())
+            .cloned()
+            .unwrap_or_default();
+
+        Override {
+            section: format!("[profile.{name}]"),
+            table,
+        }
+    }
+
+    /// The override of `target`. Its `name` and `output` are moved to the
+    /// keys of the settings they set.
+    fn target_override(&self, target: &TargetSection) -> Override {
+        let mut table = self
+            .raw
+            .get("target")
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.as_table())
+            .find(|x| x.get("name").and_then(|x| x.as_str()) == Some(&target.name))
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some(name) = table.remove("name") {
+            table.insert("target".to_owned(), name);
+        }
+
+        if let Some(output) = table.remove("output") {
+            let bundle = table
+                .entry("bundle")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+
+            if let Some(bundle) = bundle.as_table_mut() {
+                bundle.insert("output".to_owned(), output);
+            }
+        }
+
+        Override {
+            section: format!("[[target]] name = \"{}\"", target.name),
+            table,
+        }
     }
 
-    fn read_many<'a, I>(paths: I) -> Option<Result<Self>>
+    /// Read the first of `paths` that exists. Returns the path that was read
+    /// along with the file.
+    fn read_many<'a, I>(fs: &dyn FileSystem, paths: I) -> Option<Result<(&'a Path, Self)>>
     where
         I: Iterator<Item = &'a Path>,
     {
         for path in paths {
-            match Self::read(path) {
+            match Self::read(fs, path) {
                 Some(r) => {
-                    return Some(r.with_context(|| {
+                    return Some(r.map(|x| (path, x)).with_context(|| {
                         format!("failed to read config `{}`", display_path(path))
                     }))
                 }
@@ -216,11 +712,141 @@ impl ArgMatchesExt for ArgMatches {
     }
 }
 
+/// Picks the value of each setting from the places it can come from and
+/// remembers which one it came from.
+struct Resolver<'a> {
+    args: &'a ArgMatches,
+    command: clap::Command,
+    config_file: Option<PathBuf>,
+    overrides: Vec<Override>,
+    settings: Vec<Setting>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(args: &'a ArgMatches, config_file: Option<PathBuf>, overrides: Vec<Override>) -> Self {
+        Self {
+            args,
+            command: Args::command(),
+            config_file,
+            overrides,
+            settings: Vec::new(),
+        }
+    }
+
+    /// Start looking up a setting. The places it can come from must be given
+    /// in order of priority.
+    fn setting<T: Serialize>(&mut self, key: &'static str) -> Lookup<'_, 'a, T> {
+        Lookup {
+            resolver: self,
+            key,
+            value: None,
+        }
+    }
+
+    fn record<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T, origin: Origin) {
+        let value = serde_json::to_value(value).expect("settings should always serialize");
+        self.settings.push(Setting { key, value, origin });
+    }
+
+    /// Where the value of the command line argument `id` came from.
+    fn arg_origin(&self, id: &str) -> Origin {
+        let arg = self
+            .command
+            .get_arguments()
+            .find(|x| x.get_id() == id)
+            .expect("argument should exist");
+
+        if let (Some(ValueSource::EnvVariable), Some(var)) =
+            (self.args.value_source(id), arg.get_env())
+        {
+            return Origin::Env {
+                var: var.to_string_lossy().into_owned(),
+            };
+        }
+
+        let arg = match (arg.get_long(), arg.get_value_names()) {
+            (Some(x), _) => format!("--{x}"),
+            (None, Some([x, ..])) => format!("<{x}>"),
+            (None, _) => id.to_owned(),
+        };
+
+        Origin::Cli { arg }
+    }
+
+    /// Where the setting `key` of the configuration file came from. The
+    /// override that was applied last wins.
+    fn file_origin(&self, key: &str) -> Origin {
+        let path = self
+            .config_file
+            .clone()
+            .expect("settings from the config file need a config file");
+
+        let section = self
+            .overrides
+            .iter()
+            .rev()
+            .find(|x| x.contains(key))
+            .map(|x| x.section.clone());
+
+        Origin::File { path, section }
+    }
+}
+
+/// A setting being looked up by a [`Resolver`].
+struct Lookup<'r, 'a, T> {
+    resolver: &'r mut Resolver<'a>,
+    key: &'static str,
+    value: Option<(T, Origin)>,
+}
+
+impl<T: Serialize> Lookup<'_, '_, T> {
+    /// Use the value of the command line argument `id`, if it was given.
+    fn cli(mut self, id: &str, x: Option<T>) -> Self {
+        if let (None, Some(x)) = (self.value.as_ref(), x) {
+            self.value = Some((x, self.resolver.arg_origin(id)));
+        }
+
+        self
+    }
+
+    /// Use the value from the configuration file, if it has one.
+    fn file(mut self, x: Option<T>) -> Self {
+        if let (None, Some(x)) = (self.value.as_ref(), x) {
+            self.value = Some((x, self.resolver.file_origin(self.key)));
+        }
+
+        self
+    }
+
+    /// Use `default` if no other place had a value.
+    fn or(self, default: T) -> T {
+        let (x, origin) = self.value.unwrap_or((default, Origin::Default));
+        self.resolver.record(self.key, &x, origin);
+        x
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
+    /// The configuration file that was loaded, if any.
+    pub config_file: Option<PathBuf>,
+
+    /// Read files from this git revision instead of the working tree.
+    pub git_rev: Option<GitFs>,
+
+    pub mode: Mode,
     pub bundle_separators: bool,
+    pub line_markers: bool,
+    pub source_map: bool,
     pub deterministic: bool,
+    pub keep_going: bool,
+    pub include_paths: Vec<PathBuf>,
+    pub system_include_paths: Vec<PathBuf>,
+    pub public_headers: Vec<PathBuf>,
+    pub rename_statics: bool,
     pub output_file: Option<PathBuf>,
+    pub depfile: Option<PathBuf>,
+    pub watch: bool,
 
     pub header_source: Option<HeaderSource>,
 
@@ -233,142 +859,525 @@ pub struct Config {
     pub formatter: PathBuf,
     pub formatter_args: Vec<String>,
 
+    /// The name of the target, if the bundle is a target of the configuration
+    /// file.
+    pub target: Option<String>,
     pub entry: PathBuf,
+
+    /// Every setting above and where it came from.
+    pub settings: Vec<Setting>,
 }
 
-impl Config {
-    pub fn new() -> Result<Self> {
-        let args = Args::command().get_matches();
+/// The configuration file and where it came from.
+#[derive(Debug, Clone)]
+struct Loaded {
+    git_rev: Option<GitFs>,
+    config_file: Option<PathBuf>,
 
-        let file = if args.get_flag("no_config") {
-            None
-        } else if let Some(path) = args.value::<PathBuf>("config") {
-            let x = File::read(path)
-                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
-                .with_context(|| format!("failed to read config `{}`", display_path(path)))??;
+    /// The configuration file with the overrides of the target applied, if
+    /// there is one.
+    file: Option<File>,
 
-            Some(x)
-        } else {
-            let default_config_files = DEFAULT_CONFIG_FILES.iter().copied().map(Path::new);
+    /// The overrides that were applied to `file`, in the order they were
+    /// applied.
+    overrides: Vec<Override>,
 
-            match File::read_many(default_config_files) {
-                Some(x) => Some(x?),
-                None => None,
+    /// The selected profile. It is applied last, so that it takes priority
+    /// over the target.
+    profile: Option<(Override, ProfileSection)>,
+}
+
+impl Loaded {
+    /// Apply the overrides of `target` to `file`.
+    fn with_target(&self, file: &File, target: TargetSection) -> Self {
+        let mut overrides = self.overrides.clone();
+        overrides.push(file.target_override(&target));
+
+        Self {
+            file: Some(file.clone().with_target(target)),
+            overrides,
+            ..self.clone()
+        }
+    }
+}
+
+/// A section of the configuration file that overrides the rest of it, like a
+/// profile or a target.
+#[derive(Debug, Clone)]
+struct Override {
+    /// The header of the section, like `[profile.debug]`.
+    section: String,
+
+    /// The keys of the section, at the same paths as the settings they set.
+    table: toml::Table,
+}
+
+impl Override {
+    fn contains(&self, key: &str) -> bool {
+        let mut parts = key.split('.');
+        let first = parts.next().and_then(|x| self.table.get(x));
+
+        parts
+            .try_fold(first, |x, part| Some(x?.get(part)))
+            .flatten()
+            .is_some()
+    }
+}
+
+/// Read the configuration file from wherever the command line says.
+fn load(args: &ArgMatches) -> Result<Loaded> {
+    let git_rev = args
+        .get_one::<String>("rev")
+        .map(|rev| GitFs::new(rev).with_context(|| format!("failed to open git revision `{rev}`")))
+        .transpose()?;
+
+    let fs: &dyn FileSystem = match git_rev.as_ref() {
+        Some(x) => x,
+        None => &RealFs,
+    };
+
+    let loaded = load_in(args, fs)?;
+
+    Ok(Loaded { git_rev, ..loaded })
+}
+
+/// Read the configuration file from `fs`.
+fn load_in(args: &ArgMatches, fs: &dyn FileSystem) -> Result<Loaded> {
+    let (config_file, file) = if args.get_flag("no_config") {
+        (None, None)
+    } else if let Some(path) = args.value::<PathBuf>("config") {
+        let x = File::read(fs, path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+            .with_context(|| format!("failed to read config `{}`", display_path(path)))??;
+
+        (Some(path.clone()), Some(x))
+    } else {
+        let default_config_files = DEFAULT_CONFIG_FILES.iter().copied().map(Path::new);
+
+        match File::read_many(fs, default_config_files) {
+            Some(x) => {
+                let (path, x) = x?;
+                (Some(path.to_path_buf()), Some(x))
             }
+            None => (None, None),
+        }
+    };
+
+    if let (Some(path), Some(file)) = (config_file.as_ref(), file.as_ref()) {
+        let targets = &file.targets;
+
+        for (i, x) in targets.iter().enumerate() {
+            if targets[..i].iter().any(|y| y.name == x.name) {
+                bail!(
+                    "target `{}` is defined more than once in `{}`",
+                    x.name,
+                    display_path(path)
+                );
+            }
+        }
+    }
+
+    let profile = args.get_one::<String>("profile");
+
+    let profile = match (config_file.as_ref(), file.as_ref(), profile) {
+        (Some(path), Some(file), Some(name)) => {
+            let x =
+                file.profiles.get(name).cloned().ok_or_else(|| {
+                    eyre!("no profile named `{name}` in `{}`", display_path(path))
+                })?;
+
+            Some((file.profile_override(name), x))
+        }
+        (_, _, Some(name)) if !args.get_flag("no_config") => {
+            warn!("ignoring profile `{name}` because no configuration file was found");
+            None
+        }
+        _ => None,
+    };
+
+    Ok(Loaded {
+        git_rev: None,
+        config_file,
+        file,
+        overrides: Vec::new(),
+        profile,
+    })
+}
+
+impl Config {
+    /// Get the configuration for `entry`. Subcommands that work on source
+    /// files take their own entry. Without an entry, the configuration is the
+    /// one of the target given with `--target`, if any, and can otherwise
+    /// only be looked at and not used to make a bundle.
+    pub fn new(args: &ArgMatches, entry: Option<PathBuf>) -> Result<Self> {
+        Self::new_with(args, load(args)?, entry)
+    }
+
+    fn new_with(args: &ArgMatches, loaded: Loaded, entry: Option<PathBuf>) -> Result<Self> {
+        let (None, Some(name)) = (entry.as_ref(), args.get_one::<String>("target")) else {
+            return Self::with_file(args, loaded, None, entry);
         };
 
-        let bundle_separators = file
-            .as_ref()
-            .and_then(|x| x.bundle.as_ref())
-            .and_then(|x| x.separators)
-            .unwrap_or(true);
-
-        let deterministic = args
-            .flag("deterministic")
-            .or_else(|| {
-                file.as_ref()
-                    .and_then(|x| x.bundle.as_ref())
-                    .and_then(|x| x.deterministic)
-            })
-            .unwrap_or(false);
-
-        let output_file = args
-            .value::<PathBuf>("output_file")
-            .map(|x| path_not_stdio(x).cloned())
-            .or_else(|| {
-                let x = file
-                    .as_ref()
-                    .and_then(|x| x.bundle.as_ref())
-                    .and_then(|x| x.output_file.as_ref())
-                    .cloned();
-
-                Some(x)
-            })
-            .unwrap_or(None);
-
-        let header_source = file
-            .as_ref()
-            .and_then(|x| x.header.as_ref())
-            .map_or(Ok(None), |x| {
-                if !x.enable.unwrap_or(false) {
-                    return Ok(None);
-                }
+        let (Some(path), Some(file)) = (loaded.config_file.as_ref(), loaded.file.as_ref()) else {
+            bail!("no configuration file was found");
+        };
+
+        let target = file
+            .targets
+            .iter()
+            .find(|x| x.name == *name)
+            .cloned()
+            .ok_or_else(|| eyre!("no target named `{name}` in `{}`", display_path(path)))?;
+
+        let entry = target.entry.clone();
+        let loaded = loaded.with_target(file, target);
+        Self::with_file(args, loaded, Some(name.clone()), Some(entry))
+    }
+
+    /// Get the configuration of every bundle to make. This is the entry given
+    /// on the command line or, if there is none, the targets of the
+    /// configuration file.
+    pub fn all(args: &ArgMatches) -> Result<Vec<Self>> {
+        Self::all_with(args, load(args)?)
+    }
+
+    fn all_with(args: &ArgMatches, mut loaded: Loaded) -> Result<Vec<Self>> {
+        if let Some(entry) = args.get_one::<PathBuf>("entry") {
+            let x = Self::with_file(args, loaded, None, Some(entry.clone()))?;
+            return Ok(vec![x]);
+        }
+
+        let (Some(path), Some(mut file)) = (loaded.config_file.clone(), loaded.file.take()) else {
+            bail!("no entry source file was given and no configuration file was found");
+        };
+
+        let mut targets = mem::take(&mut file.targets);
+
+        if let Some(name) = args.get_one::<String>("target") {
+            targets.retain(|x| x.name == *name);
+
+            if targets.is_empty() {
+                bail!("no target named `{name}` in `{}`", display_path(&path));
+            }
+        }
+
+        if targets.is_empty() {
+            bail!(
+                "no entry source file was given and `{}` has no targets",
+                display_path(&path)
+            );
+        }
 
-                match (x.text.as_ref(), x.source.as_ref()) {
-                    (Some(x), None) => Ok(Some(HeaderSource::Text(x.clone()))),
-                    (None, Some(x)) => Ok(Some(HeaderSource::File(x.clone()))),
-                    (Some(_), Some(_)) => {
-                        bail!("both `text` and `source` were specified")
-                    }
-                    (None, None) => {
-                        bail!("bundle header needs at least one of `text` or `source` to be specified")
-                    }
+        if targets.len() > 1 {
+            for (id, name) in [("output_file", "--output"), ("depfile", "--depfile")] {
+                if args.value::<PathBuf>(id).is_some() {
+                    bail!("`{name}` cannot be used when building more than one target");
                 }
-            })?;
-
-        let no_banner = args
-            .flag("no_banner")
-            .or_else(|| {
-                file.as_ref()
-                    .and_then(|x| x.banner.as_ref())
-                    .and_then(|x| x.enable)
-                    .map(|x| !x)
+            }
+        }
+
+        let configs = targets
+            .into_iter()
+            .map(|target| {
+                let name = target.name.clone();
+                let entry = target.entry.clone();
+                let loaded = loaded.with_target(&file, target);
+
+                Self::with_file(args, loaded, Some(name), Some(entry))
             })
-            .unwrap_or(false);
-
-        let enable_quote = file
-            .as_ref()
-            .and_then(|x| x.banner.as_ref())
-            .and_then(|x| x.quote.as_ref())
-            .and_then(|x| x.enable)
-            .unwrap_or(true);
-
-        let quote_picker = file
-            .as_ref()
-            .and_then(|x| x.banner.as_ref())
-            .and_then(|x| x.quote.as_ref())
-            .and_then(|x| x.picker.as_ref())
+            .collect::<Result<Vec<_>>>()?;
+
+        check_outputs(&configs)?;
+        Ok(configs)
+    }
+
+    /// Check the configuration file for errors without making any bundle.
+    /// Returns the path of the file that was checked.
+    ///
+    /// Settings that depend on each other are checked for every target, with
+    /// the profile that would be used.
+    pub fn check(args: &ArgMatches) -> Result<PathBuf> {
+        let loaded = load(args)?;
+
+        let (Some(path), Some(file)) = (loaded.config_file.clone(), loaded.file.clone()) else {
+            bail!("no configuration file was found");
+        };
+
+        Self::with_file(args, loaded.clone(), None, None)?;
+
+        let mut configs = Vec::with_capacity(file.targets.len());
+        for target in file.targets.clone() {
+            let name = target.name.clone();
+            let entry = target.entry.clone();
+            let loaded = loaded.with_target(&file, target);
+
+            let x = Self::with_file(args, loaded, Some(name.clone()), Some(entry))
+                .with_context(|| format!("invalid target `{name}`"))?;
+            configs.push(x);
+        }
+
+        check_outputs(&configs)?;
+        Ok(path)
+    }
+
+    fn with_file(
+        args: &ArgMatches,
+        loaded: Loaded,
+        target: Option<String>,
+        entry: Option<PathBuf>,
+    ) -> Result<Self> {
+        let Loaded {
+            git_rev,
+            config_file,
+            mut file,
+            mut overrides,
+            profile,
+        } = loaded;
+
+        if let Some((x, profile)) = profile {
+            overrides.push(x);
+            file = file.map(|file| file.with_profile(profile));
+        }
+
+        let mut r = Resolver::new(args, config_file.clone(), overrides);
+
+        let bundle = file.as_ref().and_then(|x| x.bundle.as_ref());
+        let header = file.as_ref().and_then(|x| x.header.as_ref());
+        let banner = file.as_ref().and_then(|x| x.banner.as_ref());
+        let quote = banner.and_then(|x| x.quote.as_ref());
+        let formatter_section = file.as_ref().and_then(|x| x.formatter.as_ref());
+
+        let config_origin = if args.get_flag("no_config") {
+            r.arg_origin("no_config")
+        } else if args.value::<PathBuf>("config").is_some() {
+            r.arg_origin("config")
+        } else {
+            Origin::Default
+        };
+        r.record("config", &config_file, config_origin);
+
+        r.setting("profile")
+            .cli(
+                "profile",
+                args.value::<String>("profile").cloned().map(Some),
+            )
+            .or(None);
+
+        r.setting("rev")
+            .cli("rev", args.value::<String>("rev").cloned().map(Some))
+            .or(None);
+
+        r.setting("target")
+            .cli("target", args.value::<String>("target").cloned().map(Some))
+            .file(target.clone().map(Some))
+            .or(None);
+
+        match (target.is_some(), entry.as_ref()) {
+            (true, x) => r.record("entry", &x, r.file_origin("entry")),
+            (false, Some(x)) => r.record("entry", &x, r.arg_origin("entry")),
+            (false, None) => r.record("entry", &entry, Origin::Default),
+        }
+
+        let mode = r
+            .setting("bundle.mode")
+            .cli("mode", args.value::<Mode>("mode").copied())
+            .file(bundle.and_then(|x| x.mode))
+            .or(Mode::Source);
+
+        let bundle_separators = r
+            .setting("bundle.separators")
+            .file(bundle.and_then(|x| x.separators))
+            .or(true);
+
+        let line_markers = r
+            .setting("bundle.line_markers")
+            .cli("line_markers", args.flag("line_markers"))
+            .file(bundle.and_then(|x| x.line_markers))
+            .or(false);
+
+        let source_map = r
+            .setting("bundle.source_map")
+            .cli("source_map", args.flag("source_map"))
+            .file(bundle.and_then(|x| x.source_map))
+            .or(false);
+
+        let deterministic = r
+            .setting("bundle.deterministic")
+            .cli("deterministic", args.flag("deterministic"))
+            .file(bundle.and_then(|x| x.deterministic))
+            .or(false);
+
+        let keep_going = r
+            .setting("keep_going")
+            .cli("keep_going", args.flag("keep_going"))
+            .or(false);
+
+        // Include paths from the command line are searched before the ones
+        // from the configuration file.
+        let cli_include_paths: Vec<PathBuf> = args
+            .get_many::<PathBuf>("include_paths")
+            .into_iter()
+            .flatten()
             .cloned()
-            .unwrap_or(QuotePicker::All);
+            .collect();
 
-        let custom_quotes = file
-            .as_ref()
-            .and_then(|x| x.quotes.clone())
+        let file_include_paths = bundle
+            .and_then(|x| x.include_paths.clone())
             .unwrap_or_default();
 
-        let no_format = args
-            .flag("no_format")
-            .or_else(|| {
-                file.as_ref()
-                    .and_then(|x| x.formatter.as_ref())
-                    .and_then(|x| x.enable)
-                    .map(|x| !x)
-            })
-            .unwrap_or(false);
+        let include_paths_origin =
+            match (cli_include_paths.is_empty(), file_include_paths.is_empty()) {
+                (false, false) => Origin::Combined {
+                    origins: vec![
+                        r.arg_origin("include_paths"),
+                        r.file_origin("bundle.include_paths"),
+                    ],
+                },
+                (false, true) => r.arg_origin("include_paths"),
+                (true, false) => r.file_origin("bundle.include_paths"),
+                (true, true) => Origin::Default,
+            };
+
+        let include_paths: Vec<PathBuf> = cli_include_paths
+            .into_iter()
+            .chain(file_include_paths)
+            .collect();
+        r.record("bundle.include_paths", &include_paths, include_paths_origin);
 
-        let formatter = args
-            .value::<PathBuf>("formatter")
+        let system_include_paths = r
+            .setting("bundle.system_include_paths")
+            .file(bundle.and_then(|x| x.system_include_paths.clone()))
+            .or(Vec::new());
+
+        let cli_public_headers: Vec<PathBuf> = args
+            .get_many::<PathBuf>("public_headers")
+            .into_iter()
+            .flatten()
             .cloned()
-            .or_else(|| {
-                file.as_ref()
-                    .and_then(|x| x.formatter.as_ref())
-                    .and_then(|x| x.path.as_ref())
-                    .cloned()
-            })
-            .unwrap_or_else(|| PathBuf::from(DEFAULT_FORMATTER));
+            .collect();
 
-        let formatter_args = file
-            .as_ref()
-            .and_then(|x| x.formatter.as_ref())
-            .and_then(|x| x.args.clone())
-            .unwrap_or_default();
+        let public_headers = r
+            .setting("bundle.public_headers")
+            .cli(
+                "public_headers",
+                Some(cli_public_headers).filter(|x| !x.is_empty()),
+            )
+            .file(bundle.and_then(|x| x.public_headers.clone()))
+            .or(Vec::new());
 
-        let entry = args.value::<PathBuf>("entry").unwrap().clone();
+        let rename_statics = r
+            .setting("bundle.rename_statics")
+            .cli("rename_statics", args.flag("rename_statics"))
+            .file(bundle.and_then(|x| x.rename_statics))
+            .or(false);
+
+        let output_file = r
+            .setting("bundle.output")
+            .cli(
+                "output_file",
+                args.value::<PathBuf>("output_file")
+                    .map(|x| path_not_stdio(x).cloned()),
+            )
+            .file(bundle.and_then(|x| x.output_file.clone()).map(Some))
+            .or(None);
+
+        let depfile = r
+            .setting("depfile")
+            .cli(
+                "depfile",
+                args.value::<PathBuf>("depfile").cloned().map(Some),
+            )
+            .or(None);
+
+        let watch = r
+            .setting("watch")
+            .cli("watch", args.flag("watch"))
+            .or(false);
+
+        let header_source = header.map_or(Ok(None), |x| {
+            if !x.enable.unwrap_or(false) {
+                return Ok(None);
+            }
+
+            match (x.text.as_ref(), x.source.as_ref()) {
+                (Some(x), None) => Ok(Some(HeaderSource::Text(x.clone()))),
+                (None, Some(x)) => Ok(Some(HeaderSource::File(x.clone()))),
+                (Some(_), Some(_)) => {
+                    bail!("both `text` and `source` were specified")
+                }
+                (None, None) => {
+                    bail!("bundle header needs at least one of `text` or `source` to be specified")
+                }
+            }
+        })?;
+
+        r.setting("header.enable")
+            .file(header.and_then(|x| x.enable))
+            .or(false);
+
+        match header_source.as_ref() {
+            Some(HeaderSource::Text(x)) => r.record("header.text", x, r.file_origin("header.text")),
+            Some(HeaderSource::File(x)) => {
+                r.record("header.source", x, r.file_origin("header.source"))
+            }
+            None => {}
+        }
+
+        let no_banner = !r
+            .setting("banner.enable")
+            .cli("no_banner", args.flag("no_banner").map(|x| !x))
+            .file(banner.and_then(|x| x.enable))
+            .or(true);
+
+        let enable_quote = r
+            .setting("banner.quote.enable")
+            .file(quote.and_then(|x| x.enable))
+            .or(true);
+
+        let quote_picker = r
+            .setting("banner.quote.pick")
+            .file(quote.and_then(|x| x.picker.clone()))
+            .or(QuotePicker::All);
+
+        let custom_quotes = r
+            .setting("quote")
+            .file(file.as_ref().and_then(|x| x.quotes.clone()))
+            .or(Vec::new());
+
+        let no_format = !r
+            .setting("formatter.enable")
+            .cli("no_format", args.flag("no_format").map(|x| !x))
+            .file(formatter_section.and_then(|x| x.enable))
+            .or(true);
+
+        let formatter = r
+            .setting("formatter.path")
+            .cli("formatter", args.value::<PathBuf>("formatter").cloned())
+            .file(formatter_section.and_then(|x| x.path.clone()))
+            .or(PathBuf::from(DEFAULT_FORMATTER));
+
+        let formatter_args = r
+            .setting("formatter.args")
+            .file(formatter_section.and_then(|x| x.args.clone()))
+            .or(Vec::new());
 
         Ok(Self {
+            config_file,
+            git_rev,
+
+            mode,
             bundle_separators,
+            line_markers,
+            source_map,
             deterministic,
+            keep_going,
+            include_paths,
+            system_include_paths,
+            public_headers,
+            rename_statics,
             output_file,
+            depfile,
+            watch,
 
             header_source,
 
@@ -381,11 +1390,51 @@ impl Config {
             formatter,
             formatter_args,
 
-            entry,
+            target,
+            entry: entry.unwrap_or_default(),
+
+            settings: r.settings,
         })
     }
 }
 
+/// Make sure that no two targets write to the same file, or both to stdout.
+/// One of them would otherwise silently overwrite the other.
+fn check_outputs(configs: &[Config]) -> Result<()> {
+    // `None` is stdout.
+    let mut written: Vec<(Option<PathBuf>, &str)> = Vec::new();
+
+    for config in configs {
+        let name = config.target.as_deref().unwrap_or_default();
+
+        let path = config.output_file.as_deref().map(normalize_path);
+
+        if let Some((_, other)) = written.iter().find(|(x, _)| *x == path) {
+            match path {
+                Some(path) => bail!(
+                    "targets `{other}` and `{name}` both write to `{}`",
+                    display_path(&path)
+                ),
+                None => bail!(
+                    "targets `{other}` and `{name}` both write to stdout (give each target its own `output`)"
+                ),
+            }
+        }
+
+        written.push((path, name));
+    }
+
+    Ok(())
+}
+
+/// Remove the `.` components of `path`, so that `./a.c` and `a.c` compare
+/// equal.
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|x| *x != Component::CurDir)
+        .collect()
+}
+
 fn path_not_stdio(path: &PathBuf) -> Option<&PathBuf> {
     if path_is_stdio(path) {
         None
@@ -397,3 +1446,293 @@ fn path_not_stdio(path: &PathBuf) -> Option<&PathBuf> {
 fn path_is_stdio(path: &Path) -> bool {
     path.as_os_str().as_encoded_bytes().eq(b"-")
 }
+
+#[cfg(test)]
+mod tests {
+    use cbundl::vfs::MemoryFs;
+
+    use super::*;
+
+    /// Get the configuration of every target of `config`, with the extra
+    /// command line arguments in `args`.
+    fn targets(config: &str, args: &[&str]) -> Result<Vec<Config>> {
+        let mut fs = MemoryFs::new();
+        fs.insert("/project/cbundl.toml", config);
+
+        let args = Args::command().get_matches_from(
+            ["cbundl", "--config", "/project/cbundl.toml"]
+                .iter()
+                .chain(args),
+        );
+
+        let loaded = load_in(&args, &fs)?;
+        Config::all_with(&args, loaded)
+    }
+
+    #[test]
+    fn profile_overrides_target() {
+        let config = r#"
+[bundle]
+line_markers = true
+deterministic = true
+
+[profile.debug.bundle]
+line_markers = false
+
+[[target]]
+name = "frob"
+entry = "frob/main.c"
+output = "frob.c"
+bundle = { line_markers = true, deterministic = false }
+"#;
+
+        let configs = targets(config, &["--profile", "debug"]).unwrap();
+        let [config] = &configs[..] else {
+            panic!("there should be exactly one target");
+        };
+
+        assert!(!config.line_markers);
+        assert!(!config.deterministic);
+
+        let origin = |key: &str| {
+            let x = config.settings.iter().find(|x| x.key == key).unwrap();
+            x.origin.to_string()
+        };
+
+        assert!(origin("bundle.line_markers").ends_with("in `[profile.debug]`"));
+        assert!(origin("bundle.deterministic").ends_with("in `[[target]] name = \"frob\"`"));
+    }
+
+    #[test]
+    fn every_target_is_built() {
+        let file = r#"
+[bundle]
+line_markers = true
+
+[[target]]
+name = "frob"
+entry = "frob/main.c"
+output = "frob.c"
+
+[[target]]
+name = "knob"
+entry = "knob/main.c"
+output = "knob.c"
+bundle = { line_markers = false }
+"#;
+
+        let configs = targets(file, &[]).unwrap();
+        let summary: Vec<_> = configs
+            .iter()
+            .map(|x| {
+                (
+                    x.target.clone().unwrap(),
+                    x.entry.clone(),
+                    x.output_file.clone().unwrap(),
+                    x.line_markers,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (
+                    "frob".to_owned(),
+                    PathBuf::from("frob/main.c"),
+                    PathBuf::from("frob.c"),
+                    true
+                ),
+                (
+                    "knob".to_owned(),
+                    PathBuf::from("knob/main.c"),
+                    PathBuf::from("knob.c"),
+                    false
+                ),
+            ]
+        );
+
+        let configs = targets(file, &["--target", "knob"]).unwrap();
+        let [config] = &configs[..] else {
+            panic!("only `knob` should be built");
+        };
+        assert_eq!(config.target.as_deref(), Some("knob"));
+
+        let e = targets(file, &["--output", "out.c"]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "`--output` cannot be used when building more than one target"
+        );
+    }
+
+    #[test]
+    fn targets_cannot_share_an_output() {
+        let config = r#"
+[[target]]
+name = "frob"
+entry = "frob/main.c"
+output = "out/bundle.c"
+
+[[target]]
+name = "knob"
+entry = "knob/main.c"
+output = "./out/bundle.c"
+"#;
+
+        let e = targets(config, &[]).unwrap_err();
+        let e = e.to_string();
+        assert!(
+            e.starts_with("targets `frob` and `knob` both write to"),
+            "{e}"
+        );
+        assert!(e.contains("out/bundle.c"), "{e}");
+
+        let config = r#"
+[[target]]
+name = "frob"
+entry = "frob/main.c"
+
+[[target]]
+name = "knob"
+entry = "knob/main.c"
+"#;
+
+        let e = targets(config, &[]).unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("targets `frob` and `knob` both write to stdout"),
+            "{e}"
+        );
+    }
+
+    /// Run `cbundl config show` with the extra command line arguments in
+    /// `args`.
+    fn show(config: &str, args: &[&str], format: SettingsFormat) -> String {
+        let mut fs = MemoryFs::new();
+        fs.insert("/project/cbundl.toml", config);
+
+        let args = Args::command().get_matches_from(
+            [
+                "cbundl",
+                "--config",
+                "/project/cbundl.toml",
+                "config",
+                "show",
+            ]
+            .iter()
+            .chain(args),
+        );
+
+        let Some(Command::Config {
+            command: ConfigCommand::Show { entry, .. },
+        }) = Command::from_args(&args)
+        else {
+            panic!("`config show` should be parsed");
+        };
+
+        let loaded = load_in(&args, &fs).unwrap();
+        let config = Config::new_with(&args, loaded, entry).unwrap();
+        crate::settings::render(&config.settings, format).unwrap()
+    }
+
+    #[test]
+    fn args() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn config_show() {
+        let config = "[bundle]\ndeterministic = true\n";
+
+        // Settings come after the subcommand, like every other argument.
+        let out = show(
+            config,
+            &["--no-format", "--line-markers", "main.c"],
+            SettingsFormat::Toml,
+        );
+
+        assert_eq!(
+            out,
+            r#"config = "/project/cbundl.toml" # command line `--config`
+# profile is not set (default)
+# rev is not set (default)
+# target is not set (default)
+entry = "main.c" # command line `<path>`
+keep_going = false # default
+# depfile is not set (default)
+watch = false # default
+quote = [] # default
+
+[bundle]
+mode = "source" # default
+separators = true # default
+line_markers = true # command line `--line-markers`
+source_map = false # default
+deterministic = true # config file `/project/cbundl.toml`
+include_paths = [] # default
+system_include_paths = [] # default
+public_headers = [] # default
+rename_statics = false # default
+# output is not set (default)
+
+[header]
+enable = false # default
+
+[banner]
+enable = true # default
+
+[banner.quote]
+enable = true # default
+pick = "all" # default
+
+[formatter]
+enable = false # command line `--no-format`
+path = "clang-format" # default
+args = [] # default
+"#
+        );
+    }
+
+    #[test]
+    fn config_show_json() {
+        let out = show(
+            "[profile.ci.bundle]\nsource_map = true\n",
+            &["--profile", "ci", "-I", "inc", "main.c"],
+            SettingsFormat::Json,
+        );
+
+        let settings: Vec<serde_json::Value> = serde_json::from_str(&out).unwrap();
+        let setting = |key: &str| {
+            settings
+                .iter()
+                .find(|x| x["key"] == key)
+                .unwrap_or_else(|| panic!("`{key}` should be shown"))
+                .clone()
+        };
+
+        assert_eq!(
+            setting("bundle.source_map"),
+            serde_json::json!({
+                "key": "bundle.source_map",
+                "value": true,
+                "origin": {
+                    "kind": "file",
+                    "path": "/project/cbundl.toml",
+                    "section": "[profile.ci]",
+                },
+            })
+        );
+        assert_eq!(
+            setting("bundle.include_paths")["value"],
+            serde_json::json!(["inc"])
+        );
+        assert_eq!(
+            setting("bundle.include_paths")["origin"],
+            serde_json::json!({ "kind": "cli", "arg": "--include-path" })
+        );
+        assert_eq!(
+            setting("bundle.line_markers")["origin"],
+            serde_json::json!({ "kind": "default" })
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::vfs::normalize;
+
+/// A Makefile rule that says which files a bundle was made from.
+#[derive(Debug, Clone)]
+pub struct Depfile {
+    pub target: PathBuf,
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl Depfile {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out)
+            .expect("writing to String should never fail");
+        out
+    }
+
+    fn write(&self, out: &mut String) -> fmt::Result {
+        write_path(out, &self.target)?;
+        out.write_char(':')?;
+
+        // `./a.h` and `a.h` are the same file, so compare the paths without
+        // their `.` and `..` components.
+        let mut seen = Vec::with_capacity(self.dependencies.len());
+        for dependency in &self.dependencies {
+            let dependency = normalize(dependency);
+            if seen.contains(&dependency) {
+                continue;
+            }
+
+            out.write_str(" \\\n  ")?;
+            write_path(out, &dependency)?;
+            seen.push(dependency);
+        }
+
+        writeln!(out)
+    }
+}
+
+/// Write `path` escaped the same way as `cc -MD` does.
+fn write_path(out: &mut String, path: &Path) -> fmt::Result {
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => {
+                out.write_char('\\')?;
+                out.write_char(c)?;
+            }
+            '$' => out.write_str("$$")?,
+            _ => out.write_char(c)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_escaped() {
+        let depfile = Depfile {
+            target: PathBuf::from("out dir/bundle.c"),
+            dependencies: vec![
+                PathBuf::from("src/my file.h"),
+                PathBuf::from("src/#1.c"),
+                PathBuf::from("src/$x.h"),
+            ],
+        };
+
+        assert_eq!(
+            depfile.render(),
+            "out\\ dir/bundle.c: \\\n  src/my\\ file.h \\\n  src/\\#1.c \\\n  src/$$x.h\n"
+        );
+    }
+
+    #[test]
+    fn same_file_is_listed_once() {
+        let depfile = Depfile {
+            target: PathBuf::from("bundle.c"),
+            dependencies: vec![
+                PathBuf::from("./a.h"),
+                PathBuf::from("a.h"),
+                PathBuf::from("src/../b.h"),
+                PathBuf::from("b.h"),
+            ],
+        };
+
+        assert_eq!(depfile.render(), "bundle.c: \\\n  a.h \\\n  b.h\n");
+    }
+}
@@ -0,0 +1,417 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::vec;
+
+use owo_colors::OwoColorize;
+
+use crate::display::display_path;
+
+/// A range of bytes inside a source file.
+pub type Span = Range<usize>;
+
+/// A value together with the place it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Self {
+        Self { value, span }
+    }
+
+    pub fn map<U, F>(self, f: F) -> Spanned<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Spanned {
+            value: f(self.value),
+            span: self.span,
+        }
+    }
+}
+
+/// A file whose contents diagnostics can point into.
+#[derive(Debug, Clone)]
+pub struct SourceText {
+    pub path: PathBuf,
+    pub text: Arc<str>,
+}
+
+impl SourceText {
+    pub fn new(path: PathBuf, text: impl Into<Arc<str>>) -> Self {
+        Self {
+            path,
+            text: text.into(),
+        }
+    }
+
+    /// Get the 1-based line and column of the byte at `offset`.
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let before = &self.text[..offset];
+
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+        let column = self.text[line_start..offset].chars().count() + 1;
+
+        (line, column)
+    }
+
+    /// Get the span of the whole line that contains the byte at `offset`,
+    /// without the line terminator.
+    pub fn line_span(&self, offset: usize) -> Span {
+        let offset = offset.min(self.text.len());
+
+        let start = self.text[..offset].rfind('\n').map_or(0, |x| x + 1);
+        let end = self.text[offset..]
+            .find('\n')
+            .map_or(self.text.len(), |x| offset + x);
+        let end = if self.text[start..end].ends_with('\r') {
+            end - 1
+        } else {
+            end
+        };
+
+        start..end
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Label {
+    source: SourceText,
+    span: Span,
+    text: Option<String>,
+}
+
+/// The log target of diagnostics that are logged instead of returned.
+/// Diagnostics start with their own level, so loggers should not add one.
+pub const DIAGNOSTIC_LOG_TARGET: &str = "cbundl::diagnostic";
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// The bundle can't be made.
+    Error,
+    /// The bundle is made, but it might not be what was intended.
+    Warning,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+
+    fn paint(self, s: &str) -> String {
+        match self {
+            Self::Error => s.bright_red().bold().to_string(),
+            Self::Warning => s.bright_yellow().bold().to_string(),
+        }
+    }
+}
+
+/// An error report that can point at the exact place in a source file that
+/// caused it, in the style of `rustc`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    level: Level,
+    message: String,
+    labels: Vec<Label>,
+    notes: Vec<(&'static str, String)>,
+}
+
+impl Diagnostic {
+    fn new(level: Level, message: impl Into<String>) -> Self {
+        Self {
+            level,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Level::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Level::Warning, message)
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Point at `span` inside `source`.
+    #[must_use]
+    pub fn with_span(mut self, source: &SourceText, span: Span) -> Self {
+        self.labels.push(Label {
+            source: source.clone(),
+            span,
+            text: None,
+        });
+        self
+    }
+
+    /// Point at `span` inside `source` and explain it with `text`.
+    #[must_use]
+    pub fn with_label(mut self, source: &SourceText, span: Span, text: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            source: source.clone(),
+            span,
+            text: Some(text.into()),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(("note", note.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.notes.push(("help", help.into()));
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.level.paint(self.level.name()),
+            self.message.bold()
+        )?;
+
+        let gutter_width = self
+            .labels
+            .iter()
+            .map(|x| x.source.location(x.span.start).0.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(gutter_width);
+        let pipe = "|".bright_blue().bold().to_string();
+
+        for label in &self.labels {
+            let (line_no, column) = label.source.location(label.span.start);
+            let line_span = label.source.line_span(label.span.start);
+            let line = &label.source.text[line_span.clone()];
+
+            writeln!(f)?;
+            write!(
+                f,
+                "{gutter}{} {}:{line_no}:{column}",
+                "-->".bright_blue().bold(),
+                display_path(&label.source.path)
+            )?;
+
+            writeln!(f)?;
+            writeln!(f, "{gutter} {pipe}")?;
+            writeln!(
+                f,
+                "{:>gutter_width$} {pipe} {}",
+                line_no.bright_blue().bold(),
+                expand_tabs(line)
+            )?;
+
+            let caret_start = label.span.start.clamp(line_span.start, line_span.end);
+            let caret_end = label.span.end.clamp(caret_start, line_span.end);
+
+            let padding = expand_tabs(&label.source.text[line_span.start..caret_start])
+                .chars()
+                .count();
+            let carets = expand_tabs(&label.source.text[caret_start..caret_end])
+                .chars()
+                .count()
+                .max(1);
+
+            write!(
+                f,
+                "{gutter} {pipe} {}{}",
+                " ".repeat(padding),
+                self.level.paint(&"^".repeat(carets))
+            )?;
+
+            if let Some(text) = label.text.as_ref() {
+                write!(f, " {}", self.level.paint(text))?;
+            }
+        }
+
+        if !self.labels.is_empty() && !self.notes.is_empty() {
+            writeln!(f)?;
+            write!(f, "{gutter} {pipe}")?;
+        }
+
+        for (kind, note) in &self.notes {
+            writeln!(f)?;
+            write!(
+                f,
+                "{gutter} {} {}: {note}",
+                "=".bright_blue().bold(),
+                kind.bold()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+fn expand_tabs(s: &str) -> String {
+    s.replace('\t', "    ")
+}
+
+/// One or more [`Diagnostic`]s.
+#[derive(Debug, Clone)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new(diagnostics: Vec<Diagnostic>) -> Self {
+        Self(diagnostics)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(x: Diagnostic) -> Self {
+        Self(vec![x])
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+
+            diagnostic.fmt(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Remove the ANSI color codes from `s`.
+    pub(crate) fn strip_colors(s: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut iter = s.chars();
+
+        while let Some(c) = iter.next() {
+            if c == '\x1b' {
+                iter.by_ref().find(|x| *x == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+
+        out
+    }
+
+    fn render(diagnostic: &Diagnostic) -> String {
+        strip_colors(&diagnostic.to_string())
+    }
+
+    #[test]
+    fn labels_and_notes() {
+        let source = SourceText::new(PathBuf::from("a.c"), "int a;\nint a;\n");
+        let diagnostic = Diagnostic::error("`a` is defined twice")
+            .with_label(&source, 11..12, "second definition")
+            .with_span(&source, 4..5)
+            .with_help("remove one of them");
+
+        assert_eq!(
+            render(&diagnostic),
+            "\
+error: `a` is defined twice
+ --> a.c:2:5
+  |
+2 | int a;
+  |     ^ second definition
+ --> a.c:1:5
+  |
+1 | int a;
+  |     ^
+  |
+  = help: remove one of them"
+        );
+    }
+
+    #[test]
+    fn tabs_are_expanded() {
+        let source = SourceText::new(PathBuf::from("a.c"), "\tint\tb = c;\r\n");
+        let diagnostic = Diagnostic::error("unknown `c`").with_span(&source, 9..10);
+
+        assert_eq!(
+            render(&diagnostic),
+            "\
+error: unknown `c`
+ --> a.c:1:10
+  |
+1 |     int    b = c;
+  |                ^"
+        );
+    }
+
+    #[test]
+    fn gutter_fits_the_widest_line_number() {
+        let text: String = (1..=12).map(|x| format!("int x{x};\n")).collect();
+        let source = SourceText::new(PathBuf::from("a.c"), text.as_str());
+        let start = text.find("x11").unwrap();
+        let diagnostic = Diagnostic::warning("unused variables")
+            .with_span(&source, 4..6)
+            .with_label(&source, start..start + 3, "also unused")
+            .with_note("both are never read");
+
+        assert_eq!(diagnostic.level(), Level::Warning);
+        assert_eq!(
+            render(&diagnostic),
+            "\
+warning: unused variables
+  --> a.c:1:5
+   |
+ 1 | int x1;
+   |     ^^
+  --> a.c:11:5
+   |
+11 | int x11;
+   |     ^^^ also unused
+   |
+   = note: both are never read"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::string::FromUtf8Error;
+
+use thiserror::Error;
+
+use crate::diagnostic::Diagnostics;
+use crate::display::display_path;
+
+/// Everything that can go wrong while making a bundle.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The source files could not be loaded. This usually means that they
+    /// contain errors, which are described by the diagnostics.
+    #[error("{0}")]
+    Sources(#[from] Diagnostics),
+
+    #[error("stage '{stage}' failed")]
+    Stage {
+        stage: &'static str,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("failed to read header source `{}`", display_path(.path))]
+    ReadHeader {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to run formatter `{}`", display_path(.exe))]
+    RunFormatter {
+        exe: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("formatter exited with {0}")]
+    FormatterFailed(ExitStatus),
+
+    #[error("formatter stdout contains invalid UTF8")]
+    FormatterOutput(#[source] FromUtf8Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -1,16 +1,27 @@
-use std::io::Write;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
-use eyre::{bail, Context, Result};
-
-use crate::display::display_path;
+use crate::error::{Error, Result};
+use crate::line_markers;
 use crate::pipeline::Stage;
 
 #[derive(Debug, Clone)]
 pub struct Formatter {
     pub exe: PathBuf,
     pub args: Vec<String>,
+
+    /// Put back the `#line` markers in the code after it has been formatted.
+    pub line_markers: bool,
+}
+
+impl Formatter {
+    fn error(&self, source: io::Error) -> Error {
+        Error::RunFormatter {
+            exe: self.exe.clone(),
+            source,
+        }
+    }
 }
 
 impl Stage for Formatter {
@@ -25,23 +36,26 @@ impl Stage for Formatter {
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .spawn()
-            .with_context(|| format!("failed to run formatter `{}`", display_path(&self.exe)))?;
+            .map_err(|source| self.error(source))?;
 
         p.stdin
             .as_mut()
             .expect("stdin was captured but was also None")
             .write_all(code.as_bytes())
-            .context("failed to write bundle to formatter's stdin")?;
+            .map_err(|source| self.error(source))?;
 
-        let p = p.wait_with_output()?;
+        let p = p.wait_with_output().map_err(|source| self.error(source))?;
 
         if !p.status.success() {
-            bail!("formatter exited with non-zero code");
+            return Err(Error::FormatterFailed(p.status));
         }
 
-        let formatted_code =
-            String::from_utf8(p.stdout).context("formatter stdout contains invalid UTF8")?;
+        let formatted_code = String::from_utf8(p.stdout).map_err(Error::FormatterOutput)?;
 
-        Ok(formatted_code)
+        if self.line_markers {
+            Ok(line_markers::resync(&code, &formatted_code))
+        } else {
+            Ok(formatted_code)
+        }
     }
 }
@@ -0,0 +1,299 @@
+use std::fmt::{self, Write};
+
+use serde::Serialize;
+
+use crate::source::{Reference, SourceKind, Sources};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum GraphFormat {
+    Dot,
+    Json,
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Node {
+    id: usize,
+    kind: NodeKind,
+    path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum NodeKind {
+    Declaration,
+    Implementation,
+}
+
+impl NodeKind {
+    const ALL: [Self; 2] = [Self::Declaration, Self::Implementation];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Declaration => "declaration",
+            Self::Implementation => "implementation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Edge {
+    from: usize,
+    to: usize,
+    kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EdgeKind {
+    Include,
+    Impl,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Include => "include",
+            Self::Impl => "impl",
+        }
+    }
+}
+
+impl Graph {
+    fn new(sources: &Sources) -> Self {
+        let nodes = sources
+            .files()
+            .enumerate()
+            .map(|(id, x)| Node {
+                id,
+                kind: match x.kind {
+                    SourceKind::Declaration => NodeKind::Declaration,
+                    SourceKind::Implementation => NodeKind::Implementation,
+                },
+                path: x.relative_path.to_string_lossy().into_owned(),
+            })
+            .collect();
+
+        let edges = sources
+            .references()
+            .map(|(from, to, reference)| Edge {
+                from,
+                to,
+                kind: match reference {
+                    Reference::Include | Reference::SystemInclude => EdgeKind::Include,
+                    Reference::ImplFile => EdgeKind::Impl,
+                },
+            })
+            .collect();
+
+        Self { nodes, edges }
+    }
+
+    fn write_dot(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "digraph cbundl {{")?;
+
+        for node in &self.nodes {
+            let shape = match node.kind {
+                NodeKind::Declaration => "ellipse",
+                NodeKind::Implementation => "box",
+            };
+
+            writeln!(
+                out,
+                "  n{} [label={}, kind={}, shape={shape}];",
+                node.id,
+                quoted(&node.path),
+                quoted(node.kind.name())
+            )?;
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Include => "solid",
+                EdgeKind::Impl => "dashed",
+            };
+
+            writeln!(
+                out,
+                "  n{} -> n{} [label={}, style={style}];",
+                edge.from,
+                edge.to,
+                quoted(edge.kind.name())
+            )?;
+        }
+
+        writeln!(out, "}}")
+    }
+
+    fn write_mermaid(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "flowchart TD")?;
+
+        for node in &self.nodes {
+            // Mermaid labels can't escape quotes, only replace them.
+            let path = node.path.replace('"', "#quot;");
+
+            match node.kind {
+                NodeKind::Declaration => writeln!(out, "  n{}([\"{path}\"])", node.id)?,
+                NodeKind::Implementation => writeln!(out, "  n{}[\"{path}\"]", node.id)?,
+            }
+        }
+
+        for edge in &self.edges {
+            let arrow = match edge.kind {
+                EdgeKind::Include => "-->",
+                EdgeKind::Impl => "-.->",
+            };
+
+            writeln!(
+                out,
+                "  n{} {arrow}|{}| n{}",
+                edge.from,
+                edge.kind.name(),
+                edge.to
+            )?;
+        }
+
+        for kind in NodeKind::ALL {
+            let style = match kind {
+                NodeKind::Declaration => "fill:#e8f0fe,stroke:#4a6fa5",
+                NodeKind::Implementation => "fill:#fdf1e6,stroke:#b3743a",
+            };
+
+            writeln!(out, "  classDef {} {style}", kind.name())?;
+        }
+
+        for node in &self.nodes {
+            writeln!(out, "  class n{} {}", node.id, node.kind.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Render the source graph of `sources` in `format`.
+pub fn render(sources: &Sources, format: GraphFormat) -> String {
+    let graph = Graph::new(sources);
+
+    match format {
+        GraphFormat::Dot => {
+            let mut out = String::new();
+            graph
+                .write_dot(&mut out)
+                .expect("writing to String should never fail");
+            out
+        }
+        GraphFormat::Json => {
+            let mut out =
+                serde_json::to_string_pretty(&graph).expect("graph should always serialize");
+            out.push('\n');
+            out
+        }
+        GraphFormat::Mermaid => {
+            let mut out = String::new();
+            graph
+                .write_mermaid(&mut out)
+                .expect("writing to String should never fail");
+            out
+        }
+    }
+}
+
+fn quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        if matches!(c, '\\' | '"') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::source::SourcesOptions;
+    use crate::vfs::MemoryFs;
+
+    fn sources() -> Sources {
+        let mut fs = MemoryFs::new();
+        fs.insert("/src/main.c", "// cbundl: bundle\n#include \"a.h\"\n");
+        fs.insert("/src/a.h", "int a(void);\n// cbundl: impl=a.c\n");
+        fs.insert("/src/a.c", "int a(void) { return 0; }\n");
+
+        Sources::new(
+            &fs,
+            PathBuf::from("/src/main.c"),
+            &SourcesOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dot() {
+        assert_eq!(
+            render(&sources(), GraphFormat::Dot),
+            "\
+digraph cbundl {
+  n0 [label=\"main.c\", kind=\"implementation\", shape=box];
+  n1 [label=\"a.h\", kind=\"declaration\", shape=ellipse];
+  n2 [label=\"a.c\", kind=\"implementation\", shape=box];
+  n1 -> n2 [label=\"impl\", style=dashed];
+  n0 -> n1 [label=\"include\", style=solid];
+}
+"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let graph: serde_json::Value =
+            serde_json::from_str(&render(&sources(), GraphFormat::Json)).unwrap();
+
+        assert_eq!(
+            graph,
+            serde_json::json!({
+                "nodes": [
+                    { "id": 0, "kind": "implementation", "path": "main.c" },
+                    { "id": 1, "kind": "declaration", "path": "a.h" },
+                    { "id": 2, "kind": "implementation", "path": "a.c" },
+                ],
+                "edges": [
+                    { "from": 1, "to": 2, "kind": "impl" },
+                    { "from": 0, "to": 1, "kind": "include" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn mermaid() {
+        assert_eq!(
+            render(&sources(), GraphFormat::Mermaid),
+            "\
+flowchart TD
+  n0[\"main.c\"]
+  n1([\"a.h\"])
+  n2[\"a.c\"]
+  n1 -.->|impl| n2
+  n0 -->|include| n1
+  classDef declaration fill:#e8f0fe,stroke:#4a6fa5
+  classDef implementation fill:#fdf1e6,stroke:#b3743a
+  class n0 implementation
+  class n1 declaration
+  class n2 implementation
+"
+        );
+    }
+}
@@ -2,9 +2,8 @@ use std::borrow::Cow;
 use std::fs;
 use std::path::PathBuf;
 
-use eyre::{Context, Result};
-
-use crate::{display::display_path, pipeline::Stage};
+use crate::error::{Error, Result};
+use crate::pipeline::Stage;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HeaderSource {
@@ -17,7 +16,10 @@ impl HeaderSource {
         match self {
             Self::Text(x) => Ok(Cow::Borrowed(x)),
             Self::File(ref x) => fs::read_to_string(x)
-                .with_context(|| format!("failed to read banner source `{}`", display_path(x)))
+                .map_err(|source| Error::ReadHeader {
+                    path: x.clone(),
+                    source,
+                })
                 .map(Cow::Owned),
         }
     }
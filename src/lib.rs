@@ -0,0 +1,45 @@
+//! webpack but for C code.
+//!
+//! This is the library behind the `cbundl` command line tool. Most users want
+//! [`BundleOptions`], which loads the source files and makes the bundle in one
+//! go. [`Sources`] and [`Pipeline`] are there for finer control over each step.
+
+#[macro_use]
+extern crate log;
+
+mod banner;
+mod bundler;
+mod depfile;
+mod diagnostic;
+mod error;
+mod formatter;
+mod header;
+mod options;
+mod parse;
+mod pipeline;
+mod quotes;
+mod source;
+mod source_map;
+mod symbols;
+
+pub mod consts;
+pub mod display;
+pub mod graph;
+pub mod line_markers;
+pub mod unbundle;
+pub mod vfs;
+
+pub use self::banner::Banner;
+pub use self::bundler::{Bundler, Mode};
+pub use self::depfile::Depfile;
+pub use self::diagnostic::{
+    Diagnostic, Diagnostics, Level, SourceText, Span, Spanned, DIAGNOSTIC_LOG_TARGET,
+};
+pub use self::error::{Error, Result};
+pub use self::formatter::Formatter;
+pub use self::header::{Header, HeaderSource};
+pub use self::options::BundleOptions;
+pub use self::pipeline::{Bundle, Pipeline, PublicHeader, Stage};
+pub use self::quotes::{CustomQuote, QuotePicker, Quotes};
+pub use self::source::{DependencyOrder, Reference, Source, SourceKind, Sources, SourcesOptions};
+pub use self::source_map::{Mapping, SourceMap};
@@ -0,0 +1,312 @@
+use std::fmt::{self, Write};
+use std::path::Path;
+
+/// The path of the `#line` marker that ends a source file. The lines after it
+/// were written by `cbundl` and don't come from any source file.
+pub const GENERATED_PATH: &str = "<cbundl>";
+
+/// A line inside an original source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+}
+
+impl Location {
+    fn next(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            line: self.line + 1,
+        }
+    }
+}
+
+/// Write a `#line` marker which tells the compiler that the next line is
+/// `line` of `path`.
+pub fn write_marker<W>(out: &mut W, line: usize, path: &Path) -> fmt::Result
+where
+    W: Write + ?Sized,
+{
+    write_location(
+        out,
+        &Location {
+            path: path.to_string_lossy().into_owned(),
+            line,
+        },
+    )
+}
+
+/// Write a `#line` marker which tells the compiler that the next lines don't
+/// come from any source file.
+pub fn write_end_marker<W>(out: &mut W) -> fmt::Result
+where
+    W: Write + ?Sized,
+{
+    write_location(
+        out,
+        &Location {
+            path: GENERATED_PATH.to_owned(),
+            line: 1,
+        },
+    )
+}
+
+fn write_location<W>(out: &mut W, location: &Location) -> fmt::Result
+where
+    W: Write + ?Sized,
+{
+    write!(out, "#line {} \"", location.line)?;
+    for c in location.path.chars() {
+        if matches!(c, '\\' | '"') {
+            out.write_char('\\')?;
+        }
+        out.write_char(c)?;
+    }
+    writeln!(out, "\"")
+}
+
+/// Parse a `#line` marker. Returns the line number and the path, if the
+/// marker specifies one.
+fn parse_marker(line: &str) -> Option<(usize, Option<String>)> {
+    let s = line.trim_start().strip_prefix('#')?;
+    let s = s.trim_start().strip_prefix("line")?;
+    let s = s.strip_prefix(char::is_whitespace)?.trim_start();
+
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let line_no = s[..digits].parse().ok()?;
+
+    let s = s[digits..].trim();
+    let Some(s) = s.strip_prefix('"') else {
+        return Some((line_no, None));
+    };
+
+    let mut path = String::new();
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' => path.push(iter.next()?),
+            '"' => return Some((line_no, Some(path))),
+            _ => path.push(c),
+        }
+    }
+
+    None
+}
+
+pub fn is_marker(line: &str) -> bool {
+    parse_marker(line).is_some()
+}
+
+/// Find out where each line of `code` came from according to the `#line`
+/// markers inside it.
+///
+/// Marker lines themselves, lines before the first marker and lines after an
+/// end marker have no location.
+pub fn locations(code: &str) -> Vec<Option<Location>> {
+    let mut current: Option<Location> = None;
+
+    code.lines()
+        .map(|line| match parse_marker(line) {
+            Some((_, Some(path))) if path == GENERATED_PATH => {
+                current = None;
+                None
+            }
+            Some((line_no, path)) => {
+                let path = path
+                    .or_else(|| current.as_ref().map(|x| x.path.clone()))
+                    .unwrap_or_default();
+
+                current = Some(Location {
+                    path,
+                    line: line_no,
+                });
+                None
+            }
+            None => {
+                let location = current.clone();
+                current = current.as_ref().map(Location::next);
+                location
+            }
+        })
+        .collect()
+}
+
+/// Remove all `#line` markers from `code` and find out where each of the
+/// remaining lines came from.
+pub fn strip(code: &str) -> (String, Vec<Option<Location>>) {
+    let mut out = String::with_capacity(code.len());
+    let mut out_locations = Vec::new();
+
+    for (line, location) in code.lines().zip(locations(code)) {
+        if is_marker(line) {
+            continue;
+        }
+
+        out.push_str(line);
+        out.push('\n');
+        out_locations.push(location);
+    }
+
+    (out, out_locations)
+}
+
+/// Put the `#line` markers of `before` back into `after`, which is `before`
+/// after it has been reformatted.
+///
+/// A code formatter only ever changes whitespace, so each line of `after` is
+/// matched back to the line of `before` its first non-whitespace character
+/// came from. New markers are then written wherever the line numbers are no
+/// longer consecutive. This keeps markers valid even when the formatter joins
+/// or splits lines.
+pub fn resync(before: &str, after: &str) -> String {
+    let before_locations = locations(before);
+
+    let pre: Vec<(char, usize)> = before
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !is_marker(line))
+        .flat_map(|(i, line)| non_whitespace(line).map(move |c| (c, i)))
+        .collect();
+
+    let after_lines: Vec<&str> = after.lines().filter(|x| !is_marker(x)).collect();
+
+    let post: Vec<(char, usize)> = after_lines
+        .iter()
+        .enumerate()
+        .flat_map(|(i, line)| non_whitespace(line).map(move |c| (c, i)))
+        .collect();
+
+    // `None` for lines that were not matched, like blank lines, which just
+    // continue the line before them. `Some(None)` for lines that came from no
+    // source file.
+    let mut after_locations: Vec<Option<Option<&Location>>> = vec![None; after_lines.len()];
+    let mut seen = vec![false; after_lines.len()];
+
+    for (j, i) in align(&pre, &post).into_iter().enumerate() {
+        let after_line = post[j].1;
+        if seen[after_line] {
+            continue;
+        }
+
+        if let Some(i) = i {
+            seen[after_line] = true;
+            after_locations[after_line] = Some(before_locations[pre[i].1].as_ref());
+        }
+    }
+
+    let mut out = String::with_capacity(after.len());
+    let mut expected: Option<Location> = None;
+
+    for (line, location) in after_lines.iter().zip(after_locations) {
+        match location {
+            Some(Some(location)) => {
+                if expected.as_ref() != Some(location) {
+                    write_location(&mut out, location)
+                        .expect("writing to String should never fail");
+                }
+
+                expected = Some(location.clone());
+            }
+            Some(None) => {
+                if expected.is_some() {
+                    write_end_marker(&mut out).expect("writing to String should never fail");
+                }
+
+                expected = None;
+            }
+            None => {}
+        }
+
+        out.push_str(line);
+        out.push('\n');
+
+        expected = expected.as_ref().map(Location::next);
+    }
+
+    out
+}
+
+fn non_whitespace(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().filter(|c| !c.is_whitespace())
+}
+
+/// Match every character of `post` to a character of `pre`.
+///
+/// Both sequences are expected to be mostly the same with a few characters
+/// inserted or removed here and there.
+fn align(pre: &[(char, usize)], post: &[(char, usize)]) -> Vec<Option<usize>> {
+    const WINDOW: usize = 64;
+    const ANCHOR: usize = 8;
+
+    let matches_at = |i: usize, j: usize| {
+        let n = ANCHOR.min(pre.len() - i).min(post.len() - j);
+        n != 0 && (0..n).all(|k| pre[i + k].0 == post[j + k].0)
+    };
+
+    let mut out = Vec::with_capacity(post.len());
+    let (mut i, mut j) = (0, 0);
+
+    while j < post.len() {
+        if i < pre.len() && pre[i].0 == post[j].0 {
+            out.push(Some(i));
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let inserted =
+            (1..=WINDOW).find(|d| i < pre.len() && j + d < post.len() && matches_at(i, j + d));
+        let removed = (1..=WINDOW).find(|d| i + d < pre.len() && matches_at(i + d, j));
+
+        match (inserted, removed) {
+            (Some(ins), Some(rem)) if rem < ins => i += rem,
+            (Some(d), _) => {
+                out.extend((0..d).map(|_| None));
+                j += d;
+            }
+            (None, Some(d)) => i += d,
+            (None, None) => {
+                out.push((i < pre.len()).then_some(i));
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(path: &str, line: usize) -> Option<Location> {
+        Some(Location {
+            path: path.to_owned(),
+            line,
+        })
+    }
+
+    #[test]
+    fn end_marker_stops_the_file() {
+        let code =
+            "/* banner */\n#line 3 \"a.c\"\nint a;\nint b;\n#line 1 \"<cbundl>\"\n\n/* b.c */\n";
+
+        assert_eq!(
+            locations(code),
+            [None, None, at("a.c", 3), at("a.c", 4), None, None, None]
+        );
+    }
+
+    #[test]
+    fn resync_keeps_end_markers() {
+        let before = "#line 1 \"a.c\"\nint a;\n\nint b;\n#line 1 \"<cbundl>\"\n/* end */\n#line 1 \"b.c\"\nint c;\n";
+        let after = "int a;\n\n\nint b;\n/* end */\nint c;\n";
+
+        let code = resync(before, after);
+        assert_eq!(
+            code,
+            "#line 1 \"a.c\"\nint a;\n\n\n#line 3 \"a.c\"\nint b;\n#line 1 \"<cbundl>\"\n/* end */\n#line 1 \"b.c\"\nint c;\n"
+        );
+    }
+}
@@ -4,36 +4,52 @@ use std::process::ExitCode;
 use log::{Level, LevelFilter};
 use owo_colors::OwoColorize;
 
+use cbundl::{Diagnostics, DIAGNOSTIC_LOG_TARGET};
+
 #[macro_use]
 extern crate log;
 
-mod banner;
-mod bundler;
 mod cli;
 mod config;
-mod consts;
-mod display;
-mod formatter;
-mod header;
-mod parse;
-mod pipeline;
-mod quotes;
-mod source;
+mod settings;
+mod strict;
+mod watch;
 
 fn main() -> ExitCode {
     setup();
     print_debug_info();
 
     if let Err(e) = cli::run() {
-        error!("{e:#}");
+        log_error(&e);
         ExitCode::FAILURE
     } else {
         ExitCode::SUCCESS
     }
 }
 
+/// Log `e` and its causes. If it was caused by diagnostics, they are logged
+/// first, on their own, since they already say that they are errors.
+pub fn log_error(e: &eyre::Report) {
+    let Some(diagnostics) = e.root_cause().downcast_ref::<Diagnostics>() else {
+        error!("{e:#}");
+        return;
+    };
+
+    error!(target: DIAGNOSTIC_LOG_TARGET, "{diagnostics}");
+
+    let context: Vec<String> = e
+        .chain()
+        .take_while(|x| !x.is::<Diagnostics>())
+        .map(ToString::to_string)
+        .collect();
+
+    if !context.is_empty() {
+        error!("{}", context.join(": "));
+    }
+}
+
 fn setup() {
-    use crate::consts::BUILD_IS_DEBUG;
+    use cbundl::consts::BUILD_IS_DEBUG;
 
     color_eyre::install().unwrap();
 
@@ -48,6 +64,10 @@ fn setup() {
             },
         )
         .format(|out, record| {
+            if record.target() == DIAGNOSTIC_LOG_TARGET {
+                return writeln!(out, "{}", record.args());
+            }
+
             let level_str = match record.level() {
                 Level::Error => "error".bright_red().bold().to_string(),
                 Level::Warn => "warn".bright_yellow().bold().to_string(),
@@ -62,7 +82,7 @@ fn setup() {
 }
 
 fn print_debug_info() {
-    use crate::consts::{RUSTC_VERSION, SHORT_VERSION};
+    use cbundl::consts::{RUSTC_VERSION, SHORT_VERSION};
 
     debug!("version: {SHORT_VERSION}");
     debug!("rustc version: {RUSTC_VERSION}");
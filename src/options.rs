@@ -0,0 +1,335 @@
+use std::path::PathBuf;
+
+use crate::banner::Banner;
+use crate::bundler::{Bundler, Mode};
+use crate::consts::DEFAULT_FORMATTER;
+use crate::error::{Error, Result};
+use crate::formatter::Formatter;
+use crate::header::{Header, HeaderSource};
+use crate::pipeline::{Bundle, Pipeline};
+use crate::quotes::{CustomQuote, QuotePicker, Quotes};
+use crate::source::{SourceKind, Sources, SourcesOptions};
+use crate::vfs::{FileSystem, RealFs};
+
+/// Everything needed to make a bundle.
+///
+/// The defaults are the same as the ones of the command line tool.
+///
+/// ```no_run
+/// use cbundl::BundleOptions;
+///
+/// let bundle = BundleOptions::new("src/main.c")
+///     .include_path("src")
+///     .banner(false)
+///     .format(false)
+///     .bundle()?;
+///
+/// std::fs::write("bundle.c", bundle.code)?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct BundleOptions {
+    entry: PathBuf,
+    sources: SourcesOptions,
+
+    mode: Mode,
+    header_name: Option<String>,
+    separators: bool,
+    line_markers: bool,
+    source_map: bool,
+    deterministic: bool,
+    revision: Option<String>,
+
+    header: Option<HeaderSource>,
+
+    banner: bool,
+    quotes: bool,
+    quote_picker: QuotePicker,
+    custom_quotes: Vec<CustomQuote>,
+
+    format: bool,
+    formatter: PathBuf,
+    formatter_args: Vec<String>,
+}
+
+impl BundleOptions {
+    /// Bundle the source file at `entry` and everything it depends on.
+    pub fn new(entry: impl Into<PathBuf>) -> Self {
+        Self {
+            entry: entry.into(),
+            sources: SourcesOptions::default(),
+
+            mode: Mode::Source,
+            header_name: None,
+            separators: true,
+            line_markers: false,
+            source_map: false,
+            deterministic: false,
+            revision: None,
+
+            header: None,
+
+            banner: true,
+            quotes: true,
+            quote_picker: QuotePicker::All,
+            custom_quotes: Vec::new(),
+
+            format: true,
+            formatter: PathBuf::from(DEFAULT_FORMATTER),
+            formatter_args: Vec::new(),
+        }
+    }
+
+    /// Report every error in the source files instead of stopping at the
+    /// first one.
+    #[must_use]
+    pub fn keep_going(mut self, x: bool) -> Self {
+        self.sources.keep_going = x;
+        self
+    }
+
+    /// Add a directory to search for bundled includes.
+    #[must_use]
+    pub fn include_path(mut self, x: impl Into<PathBuf>) -> Self {
+        self.sources.include_paths.push(x.into());
+        self
+    }
+
+    /// Add a directory to search for bundled `<...>` includes.
+    #[must_use]
+    pub fn system_include_path(mut self, x: impl Into<PathBuf>) -> Self {
+        self.sources.system_include_paths.push(x.into());
+        self
+    }
+
+    /// Rename `static` symbols that more than one implementation file
+    /// defines, instead of failing. Each of them is prefixed with the path
+    /// of its file.
+    #[must_use]
+    pub fn rename_statics(mut self, x: bool) -> Self {
+        self.sources.rename_statics = x;
+        self
+    }
+
+    /// What kind of bundle to make. In [`Mode::SingleHeader`], the entry is
+    /// the public header of a library.
+    #[must_use]
+    pub fn mode(mut self, x: Mode) -> Self {
+        self.mode = x;
+        self.sources.entry_kind = match x {
+            Mode::Source => SourceKind::Implementation,
+            Mode::SingleHeader | Mode::Amalgamation => SourceKind::Declaration,
+        };
+        self
+    }
+
+    /// Add a public header to an amalgamation. Its declarations, and the
+    /// ones of everything it includes, go in the header of the amalgamation.
+    /// If none are given, the entry is the only public header.
+    #[must_use]
+    pub fn public_header(mut self, x: impl Into<PathBuf>) -> Self {
+        self.sources.public_headers.push(x.into());
+        self
+    }
+
+    /// The file name the `.c` file of an amalgamation includes its header by.
+    /// Defaults to the file name of the entry.
+    #[must_use]
+    pub fn header_name(mut self, x: impl Into<String>) -> Self {
+        self.header_name = Some(x.into());
+        self
+    }
+
+    /// Add separators between the contents of each source file.
+    #[must_use]
+    pub fn separators(mut self, x: bool) -> Self {
+        self.separators = x;
+        self
+    }
+
+    /// Emit `#line` markers that point at the original source files.
+    #[must_use]
+    pub fn line_markers(mut self, x: bool) -> Self {
+        self.line_markers = x;
+        self
+    }
+
+    /// Build a [`SourceMap`](crate::SourceMap) for the bundle.
+    #[must_use]
+    pub fn source_map(mut self, x: bool) -> Self {
+        self.source_map = x;
+        self
+    }
+
+    /// Always produce the same bundle for the same source files.
+    #[must_use]
+    pub fn deterministic(mut self, x: bool) -> Self {
+        self.deterministic = x;
+        self
+    }
+
+    /// Record the revision the source files were read from in the banner and
+    /// the separators. See [`GitFs::describe`](crate::vfs::GitFs::describe).
+    #[must_use]
+    pub fn revision(mut self, x: Option<String>) -> Self {
+        self.revision = x;
+        self
+    }
+
+    /// Put a custom header at the very top of the bundle.
+    #[must_use]
+    pub fn header(mut self, x: Option<HeaderSource>) -> Self {
+        self.header = x;
+        self
+    }
+
+    /// Put the banner at the top of the bundle.
+    #[must_use]
+    pub fn banner(mut self, x: bool) -> Self {
+        self.banner = x;
+        self
+    }
+
+    /// Put a quote inside the banner.
+    #[must_use]
+    pub fn quotes(mut self, x: bool) -> Self {
+        self.quotes = x;
+        self
+    }
+
+    #[must_use]
+    pub fn quote_picker(mut self, x: QuotePicker) -> Self {
+        self.quote_picker = x;
+        self
+    }
+
+    #[must_use]
+    pub fn custom_quotes(mut self, x: Vec<CustomQuote>) -> Self {
+        self.custom_quotes = x;
+        self
+    }
+
+    /// Pass the bundle through the formatter.
+    #[must_use]
+    pub fn format(mut self, x: bool) -> Self {
+        self.format = x;
+        self
+    }
+
+    /// The formatter executable. It must format the code from stdin and write
+    /// it to stdout.
+    #[must_use]
+    pub fn formatter(mut self, x: impl Into<PathBuf>) -> Self {
+        self.formatter = x.into();
+        self
+    }
+
+    #[must_use]
+    pub fn formatter_args(mut self, x: Vec<String>) -> Self {
+        self.formatter_args = x;
+        self
+    }
+
+    /// Load the source files.
+    pub fn sources(&self) -> Result<Sources> {
+        self.sources_in(&RealFs)
+    }
+
+    /// Load the source files from `fs` instead of the real filesystem.
+    pub fn sources_in<F: FileSystem + ?Sized>(&self, fs: &F) -> Result<Sources> {
+        let mut options = self.sources.clone();
+
+        if self.mode == Mode::Amalgamation && options.public_headers.is_empty() {
+            options.public_headers.push(self.entry.clone());
+        }
+
+        let sources = Sources::new(fs, self.entry.clone(), &options)?;
+        Ok(sources)
+    }
+
+    /// Build the pipeline that turns the source files into the bundle.
+    pub fn pipeline(&self) -> Pipeline {
+        // The source map is built from line markers, so they must be emitted
+        // even if they don't end up in the bundle.
+        let line_markers = self.line_markers || self.source_map;
+
+        let header_name = self.header_name.clone().unwrap_or_else(|| {
+            self.entry
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+
+        let bundler = Bundler {
+            mode: self.mode,
+            header_name,
+            separators: self.separators,
+            line_markers,
+            revision: self.revision.clone(),
+        };
+
+        let header = self.header.clone().map(|source| Header { source });
+
+        let quotes = self.quotes.then(|| Quotes {
+            deterministic: self.deterministic,
+            picker: self.quote_picker.clone(),
+            custom_quotes: self.custom_quotes.clone(),
+        });
+
+        let banner = self.banner.then_some(Banner {
+            deterministic: self.deterministic,
+            quotes,
+            revision: self.revision.clone(),
+        });
+
+        let formatter = self.format.then(|| Formatter {
+            exe: self.formatter.clone(),
+            args: self.formatter_args.clone(),
+            line_markers,
+        });
+
+        Pipeline {
+            bundler,
+            header,
+            banner,
+            formatter,
+            source_map: self.source_map,
+            strip_line_markers: !self.line_markers,
+        }
+    }
+
+    /// Load the source files and make the bundle.
+    pub fn bundle(&self) -> Result<Bundle> {
+        self.bundle_in(&RealFs)
+    }
+
+    /// Load the source files from `fs` and make the bundle.
+    pub fn bundle_in<F: FileSystem + ?Sized>(&self, fs: &F) -> Result<Bundle> {
+        let sources = self.sources_in(fs)?;
+
+        let mut pipeline = self.pipeline();
+        if let Some(HeaderSource::File(path)) = self.header.as_ref() {
+            let text = fs
+                .read_to_string(path)
+                .map_err(|source| Error::ReadHeader {
+                    path: path.clone(),
+                    source,
+                })?;
+
+            pipeline.header = Some(Header {
+                source: HeaderSource::Text(text),
+            });
+        }
+
+        let mut bundle = pipeline.process(&sources)?;
+
+        if let Some(HeaderSource::File(path)) = self.header.as_ref() {
+            let path = fs.canonicalize(path).unwrap_or_else(|_| path.clone());
+            if !bundle.dependencies.contains(&path) {
+                bundle.dependencies.push(path);
+            }
+        }
+
+        Ok(bundle)
+    }
+}
@@ -0,0 +1,395 @@
+use super::tokens::{TokenKind, Tokens};
+
+/// The storage class of a declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    Default,
+    Static,
+    Extern,
+    Typedef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclaratorKind {
+    Function,
+    Object,
+}
+
+/// One name declared by a declaration.
+#[derive(Debug, Clone)]
+pub struct Declarator {
+    /// Index of the token of the name.
+    pub name: usize,
+    pub kind: DeclaratorKind,
+    pub initialized: bool,
+}
+
+/// A declaration at file scope, like `static int x = 1, y;` or a function
+/// definition.
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    /// Indices of the tokens of the declaration, without the `;` after it.
+    pub tokens: std::ops::Range<usize>,
+
+    /// The declaration is a function definition with a body.
+    pub body: bool,
+
+    /// The declaration has the `inline` specifier.
+    pub inline: bool,
+
+    pub storage: Storage,
+    pub declarators: Vec<Declarator>,
+}
+
+impl Declaration {
+    /// Whether `declarator` defines its name instead of only declaring it.
+    /// Tentative definitions, like `int x;`, count as definitions.
+    pub fn defines(&self, declarator: &Declarator) -> bool {
+        match (self.storage, declarator.kind) {
+            (Storage::Typedef, _) => false,
+            (_, DeclaratorKind::Function) => self.body,
+            (Storage::Extern, DeclaratorKind::Object) => declarator.initialized,
+            (_, DeclaratorKind::Object) => true,
+        }
+    }
+}
+
+/// Words that can never be the name of a declarator.
+const KEYWORDS: &[&str] = &[
+    "_Alignof",
+    "_Atomic",
+    "_Bool",
+    "_Complex",
+    "_Imaginary",
+    "_Noreturn",
+    "_Thread_local",
+    "__const",
+    "__extension__",
+    "__inline",
+    "__inline__",
+    "__restrict",
+    "__restrict__",
+    "__signed__",
+    "__thread",
+    "__volatile__",
+    "alignof",
+    "auto",
+    "bool",
+    "char",
+    "const",
+    "constexpr",
+    "double",
+    "extern",
+    "float",
+    "inline",
+    "int",
+    "long",
+    "noreturn",
+    "register",
+    "restrict",
+    "short",
+    "signed",
+    "static",
+    "thread_local",
+    "typedef",
+    "unsigned",
+    "void",
+    "volatile",
+];
+
+/// Words that are followed by a parenthesized group that is not part of the
+/// declarator.
+const WITH_ARGUMENTS: &[&str] = &[
+    "__asm",
+    "__asm__",
+    "__attribute",
+    "__attribute__",
+    "__declspec",
+    "__typeof__",
+    "_Alignas",
+    "alignas",
+    "asm",
+    "typeof",
+    "typeof_unqual",
+];
+
+/// Find the declarations at file scope of `tokens`.
+///
+/// This understands just enough C to find the names that each declaration
+/// declares. Code that relies on macros to look like C, like a macro that
+/// expands to a whole declaration without a `;` after it, can confuse it.
+pub fn declarations(tokens: &Tokens<'_>) -> Vec<Declaration> {
+    let code: Vec<usize> = (0..tokens.tokens.len())
+        .filter(|x| !tokens.tokens[*x].directive)
+        .collect();
+
+    let parser = Parser {
+        tokens,
+        code: &code,
+    };
+
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut extern_c = 0usize;
+    let mut i = 0;
+
+    while i < code.len() {
+        match parser.text(i) {
+            "{" if depth == 0 && i == start + 2 && parser.is_extern_c(start) => {
+                // `extern "C" {` from a header that is also used from C++.
+                extern_c += 1;
+                start = i + 1;
+            }
+            "{" if depth == 0 && parser.is_function_body(start..i) => {
+                let end = parser.matching(i);
+                out.extend(parser.declaration(start..end + 1, Some(i - start)));
+                start = end + 1;
+                i = end;
+            }
+            "(" | "[" | "{" => depth += 1,
+            "}" if depth == 0 && extern_c > 0 => {
+                extern_c -= 1;
+                start = i + 1;
+            }
+            ")" | "]" | "}" => depth = depth.saturating_sub(1),
+            ";" if depth == 0 => {
+                out.extend(parser.declaration(start..i, None));
+                start = i + 1;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    out
+}
+
+struct Parser<'a, 't> {
+    tokens: &'a Tokens<'t>,
+
+    /// Indices of the tokens that are not part of a directive.
+    code: &'a [usize],
+}
+
+impl Parser<'_, '_> {
+    fn text(&self, i: usize) -> &str {
+        self.tokens.text(&self.tokens.tokens[self.code[i]])
+    }
+
+    fn kind(&self, i: usize) -> TokenKind {
+        self.tokens.tokens[self.code[i]].kind
+    }
+
+    fn is_extern_c(&self, i: usize) -> bool {
+        self.text(i) == "extern" && self.kind(i + 1) == TokenKind::Literal
+    }
+
+    /// Find the bracket that closes the one at `i`, or the last token if it
+    /// is never closed.
+    fn matching(&self, i: usize) -> usize {
+        let mut depth = 0usize;
+
+        for j in i..self.code.len() {
+            match self.text(j) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return j;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.code.len() - 1
+    }
+
+    /// Find the bracket that opens the one that `i` closes.
+    fn opening(&self, i: usize) -> Option<usize> {
+        let mut depth = 0usize;
+
+        for j in (0..=i).rev() {
+            match self.text(j) {
+                ")" | "]" | "}" => depth += 1,
+                "(" | "[" | "{" => {
+                    depth = depth.checked_sub(1)?;
+                    if depth == 0 {
+                        return Some(j);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Whether a `{` after the tokens in `head` starts the body of a function
+    /// instead of an initializer or the body of a `struct`.
+    fn is_function_body(&self, head: std::ops::Range<usize>) -> bool {
+        let mut depth = 0usize;
+        let mut last = None;
+
+        for i in head {
+            match self.text(i) {
+                "=" if depth == 0 => return false,
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+
+            if depth == 0 {
+                last = Some(i);
+            }
+        }
+
+        // The parameter list is the last thing before the body, apart from
+        // attributes.
+        let Some(mut last) = last else {
+            return false;
+        };
+
+        loop {
+            if self.text(last) != ")" {
+                return false;
+            }
+
+            match self.opening(last) {
+                Some(x) if x > 0 && WITH_ARGUMENTS.contains(&self.text(x - 1)) => {
+                    if x < 2 {
+                        return false;
+                    }
+                    last = x - 2;
+                }
+                Some(_) => return true,
+                None => return false,
+            }
+        }
+    }
+
+    fn declaration(
+        &self,
+        range: std::ops::Range<usize>,
+        body: Option<usize>,
+    ) -> Option<Declaration> {
+        let head = range.start..body.map_or(range.end, |x| range.start + x);
+
+        let first = self.text(*head.clone().peekable().peek()?);
+        if matches!(first, "_Static_assert" | "static_assert") {
+            return None;
+        }
+
+        let mut storage = Storage::Default;
+        let mut inline = false;
+        let mut parts = Vec::new();
+        let mut part_start = head.start;
+        let mut depth = 0usize;
+
+        for i in head.clone() {
+            match self.text(i) {
+                "typedef" if depth == 0 => storage = Storage::Typedef,
+                "static" if depth == 0 && storage != Storage::Typedef => storage = Storage::Static,
+                "extern" if depth == 0 && storage == Storage::Default => storage = Storage::Extern,
+                "inline" | "__inline" | "__inline__" if depth == 0 => inline = true,
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                "," if depth == 0 => {
+                    parts.push(part_start..i);
+                    part_start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(part_start..head.end);
+
+        let declarators = parts
+            .into_iter()
+            .filter_map(|part| {
+                let mut depth = 0usize;
+                let equals = part.clone().find(|i| {
+                    match self.text(*i) {
+                        "(" | "[" | "{" => depth += 1,
+                        ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                        "=" => return depth == 0,
+                        _ => {}
+                    }
+                    false
+                });
+
+                let end = equals.unwrap_or(part.end);
+                let (name, kind) = self.declarator_name(part.start..end)?;
+
+                Some(Declarator {
+                    name: self.code[name],
+                    kind,
+                    initialized: equals.is_some(),
+                })
+            })
+            .collect();
+
+        Some(Declaration {
+            tokens: self.code[range.start]..self.code[range.end - 1] + 1,
+            body: body.is_some(),
+            inline,
+            storage,
+            declarators,
+        })
+    }
+
+    /// Find the name of the declarator in `range`. The range can start with
+    /// the specifiers of the declaration, like `static const char`.
+    fn declarator_name(&self, range: std::ops::Range<usize>) -> Option<(usize, DeclaratorKind)> {
+        let mut last = None;
+        let mut tag = false;
+        let mut i = range.start;
+
+        while i < range.end {
+            let text = self.text(i);
+
+            match self.kind(i) {
+                TokenKind::Ident
+                    if WITH_ARGUMENTS.contains(&text)
+                        && i + 1 < range.end
+                        && self.text(i + 1) == "(" =>
+                {
+                    i = self.matching(i + 1);
+                }
+                TokenKind::Ident if WITH_ARGUMENTS.contains(&text) => {}
+                TokenKind::Ident if matches!(text, "struct" | "union" | "enum") => tag = true,
+                TokenKind::Ident if KEYWORDS.contains(&text) => {}
+                TokenKind::Ident if tag => tag = false,
+                TokenKind::Ident => last = Some(i),
+                TokenKind::Punct if text == "{" => {
+                    tag = false;
+                    i = self.matching(i);
+                }
+                TokenKind::Punct if text == "[" && i + 1 < range.end && self.text(i + 1) == "[" => {
+                    // A C23 attribute.
+                    i = self.matching(i);
+                }
+                TokenKind::Punct if text == "[" => break,
+                TokenKind::Punct if text == "(" => {
+                    if last.is_some() && last == i.checked_sub(1) {
+                        return Some((i - 1, DeclaratorKind::Function));
+                    }
+
+                    // Something like `(*name)(int)`.
+                    let close = self.matching(i).min(range.end);
+                    if let Some((x, _)) = self.declarator_name(i + 1..close) {
+                        return Some((x, DeclaratorKind::Object));
+                    }
+
+                    i = close;
+                }
+                _ => {}
+            }
+
+            i += 1;
+        }
+
+        last.map(|x| (x, DeclaratorKind::Object))
+    }
+}
@@ -2,59 +2,274 @@ use std::path::PathBuf;
 
 use thiserror::Error;
 
-use super::{consume_until_whitespace, consume_whitespace, consume_word};
+use crate::diagnostic::{Span, Spanned};
+
+use super::Cursor;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Directive {
     Bundle,
-    ImplFile(PathBuf),
+    ImplFile(Spanned<PathBuf>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
 pub enum ParseDirectiveError {
     #[error("missing directive keyword")]
-    MissingKeyword,
-    #[error("invalid directive keyword (expected: 'bundle', 'impl')")]
-    InvalidKeyword,
-    #[error("invalid directive syntax: {0}")]
-    InvalidSyntax(&'static str),
+    MissingKeyword { span: Span },
+    #[error("invalid directive keyword")]
+    InvalidKeyword { span: Span },
+    #[error("invalid directive syntax")]
+    InvalidSyntax { span: Span, help: &'static str },
+}
+
+impl ParseDirectiveError {
+    /// Span of the error inside the line that was parsed.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::MissingKeyword { span }
+            | Self::InvalidKeyword { span }
+            | Self::InvalidSyntax { span, .. } => span.clone(),
+        }
+    }
+
+    pub fn help(&self) -> &'static str {
+        match self {
+            Self::MissingKeyword { .. } | Self::InvalidKeyword { .. } => {
+                "expected one of: 'bundle', 'impl'"
+            }
+            Self::InvalidSyntax { help, .. } => help,
+        }
+    }
+}
+
+/// The different ways a directive can be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    /// `// cbundl: ...` or `/* cbundl: ... */`
+    Comment,
+    /// `#pragma cbundl ...`
+    Pragma,
 }
 
 impl Directive {
     pub fn try_parse(s: &str) -> Option<Result<Self, ParseDirectiveError>> {
+        let line = s;
         let s = s.trim_start();
-        let s = s.strip_prefix("// cbundl:")?;
 
-        Some(parse_body(s))
+        if let Some(s) = s.strip_prefix("//") {
+            let s = strip_directive_prefix(s)?;
+            Some(parse_body(Cursor::at(line, s), Syntax::Comment))
+        } else if let Some(s) = s.strip_prefix("/*") {
+            let s = strip_directive_prefix(s)?;
+            let start = line.len() - s.len();
+
+            let line_end = line.trim_end().len();
+            let Some(end) = s.find("*/").map(|x| start + x) else {
+                return Some(Err(ParseDirectiveError::InvalidSyntax {
+                    span: line_end..line_end,
+                    help: "directive comment must end on the same line with '*/'",
+                }));
+            };
+
+            let rest = end + "*/".len();
+            let code = rest + (line[rest..].len() - line[rest..].trim_start().len());
+            if code < line_end {
+                return Some(Err(ParseDirectiveError::InvalidSyntax {
+                    span: code..line_end,
+                    help: "code after the directive must go on its own line",
+                }));
+            }
+
+            Some(parse_body(
+                Cursor::new(&line[..end], start),
+                Syntax::Comment,
+            ))
+        } else if let Some(s) = s.strip_prefix('#') {
+            let s = s.trim_start().strip_prefix("pragma")?;
+            let s = strip_word(s, "cbundl")?;
+            Some(parse_body(Cursor::at(line, s), Syntax::Pragma))
+        } else {
+            None
+        }
     }
 }
 
-fn parse_body(s: &str) -> Result<Directive, ParseDirectiveError> {
-    let mut iter = s.chars().peekable();
+/// Strip the ` cbundl:` that follows the `//` or `/*` of a directive. Exactly
+/// one space has to come before `cbundl:`.
+fn strip_directive_prefix(s: &str) -> Option<&str> {
+    s.strip_prefix(" cbundl:")
+}
 
-    consume_whitespace(&mut iter, false);
-    let keyword = consume_word(&mut iter).ok_or(ParseDirectiveError::MissingKeyword)?;
+fn strip_word<'a>(s: &'a str, word: &str) -> Option<&'a str> {
+    let s = s.strip_prefix(char::is_whitespace)?;
+    let s = s.trim_start().strip_prefix(word)?;
+
+    if s.is_empty() || s.starts_with(char::is_whitespace) {
+        Some(s)
+    } else {
+        None
+    }
+}
 
-    match keyword.as_str() {
+fn parse_body(mut iter: Cursor<'_>, syntax: Syntax) -> Result<Directive, ParseDirectiveError> {
+    iter.consume_whitespace();
+    let keyword = iter
+        .consume_word()
+        .ok_or_else(|| ParseDirectiveError::MissingKeyword {
+            span: iter.next_span(),
+        })?;
+
+    match keyword.value {
         "bundle" => Ok(Directive::Bundle),
-        "impl" => {
-            consume_whitespace(&mut iter, false);
-            let x = iter.next().ok_or(ParseDirectiveError::InvalidSyntax(
-                "missing '=' for impl directive",
-            ))?;
-            if x != '=' {
-                return Err(ParseDirectiveError::InvalidSyntax(
-                    "expected '=' after 'impl' keyword",
-                ));
-            }
+        "impl" => match syntax {
+            Syntax::Comment => parse_impl_comment(iter),
+            Syntax::Pragma => parse_impl_pragma(iter),
+        },
+        _ => Err(ParseDirectiveError::InvalidKeyword { span: keyword.span }),
+    }
+}
+
+fn parse_impl_comment(mut iter: Cursor<'_>) -> Result<Directive, ParseDirectiveError> {
+    iter.consume_whitespace();
+    let span = iter.next_span();
+    match iter.next() {
+        Some('=') => {}
+        None => {
+            return Err(ParseDirectiveError::InvalidSyntax {
+                span,
+                help: "missing '=' for impl directive",
+            })
+        }
+        Some(_) => {
+            return Err(ParseDirectiveError::InvalidSyntax {
+                span,
+                help: "expected '=' after 'impl'",
+            })
+        }
+    }
+
+    iter.consume_whitespace();
+    let path =
+        iter.consume_until_whitespace()
+            .ok_or_else(|| ParseDirectiveError::InvalidSyntax {
+                span: iter.here(),
+                help: "missing implementation file path",
+            })?;
+
+    Ok(Directive::ImplFile(path.map(PathBuf::from)))
+}
+
+fn parse_impl_pragma(mut iter: Cursor<'_>) -> Result<Directive, ParseDirectiveError> {
+    iter.consume_whitespace();
+    let span = iter.next_span();
+    if iter.next() != Some('"') {
+        return Err(ParseDirectiveError::InvalidSyntax {
+            span,
+            help: "expected '\"' before implementation file path",
+        });
+    }
+
+    let path =
+        iter.consume_while(|c| c != '"')
+            .ok_or_else(|| ParseDirectiveError::InvalidSyntax {
+                span: iter.next_span(),
+                help: "missing implementation file path",
+            })?;
+
+    if iter.next() != Some('"') {
+        return Err(ParseDirectiveError::InvalidSyntax {
+            span: iter.here(),
+            help: "expected '\"' after implementation file path",
+        });
+    }
+
+    Ok(Directive::ImplFile(path.map(PathBuf::from)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            consume_whitespace(&mut iter, false);
-            let path = consume_until_whitespace(&mut iter).ok_or(
-                ParseDirectiveError::InvalidSyntax("missing implementation file path"),
-            )?;
+    fn parse(s: &str) -> Option<Result<Directive, ParseDirectiveError>> {
+        Directive::try_parse(s)
+    }
+
+    fn impl_file(s: &str) -> PathBuf {
+        match parse(s) {
+            Some(Ok(Directive::ImplFile(x))) => x.value,
+            x => panic!("`{s}` should be an impl directive, got {x:?}"),
+        }
+    }
+
+    fn help(s: &str) -> &'static str {
+        match parse(s) {
+            Some(Err(e)) => e.help(),
+            x => panic!("`{s}` should be an invalid directive, got {x:?}"),
+        }
+    }
 
-            Ok(Directive::ImplFile(PathBuf::from(path)))
+    #[test]
+    fn every_syntax() {
+        for s in [
+            "// cbundl: bundle",
+            "  // cbundl: bundle",
+            "/* cbundl: bundle */",
+            "/* cbundl: bundle*/  ",
+            "#pragma cbundl bundle",
+            "# pragma  cbundl  bundle",
+        ] {
+            assert_eq!(parse(s), Some(Ok(Directive::Bundle)), "{s}");
         }
-        _ => Err(ParseDirectiveError::InvalidKeyword),
+
+        assert_eq!(impl_file("// cbundl: impl=a.c"), PathBuf::from("a.c"));
+        assert_eq!(impl_file("/* cbundl: impl = a.c */"), PathBuf::from("a.c"));
+        assert_eq!(
+            impl_file("#pragma cbundl impl \"a b.c\""),
+            PathBuf::from("a b.c")
+        );
+    }
+
+    #[test]
+    fn other_lines_are_not_directives() {
+        for s in [
+            "//cbundl: bundle",
+            "//  cbundl: bundle",
+            "/*cbundl: bundle */",
+            "// cbundl bundle",
+            "#pragma once",
+            "#pragma cbundler bundle",
+            "int x; // cbundl: bundle",
+        ] {
+            assert_eq!(parse(s), None, "{s}");
+        }
+    }
+
+    #[test]
+    fn rejected_forms() {
+        assert_eq!(
+            help("/* cbundl: bundle"),
+            "directive comment must end on the same line with '*/'"
+        );
+        assert_eq!(
+            help("/* cbundl: bundle */ int x;"),
+            "code after the directive must go on its own line"
+        );
+        assert_eq!(
+            parse("/* cbundl: bundle */ int x;")
+                .unwrap()
+                .unwrap_err()
+                .span(),
+            21..27
+        );
+        assert_eq!(help("// cbundl: frob"), "expected one of: 'bundle', 'impl'");
+        assert_eq!(help("// cbundl: impl"), "missing '=' for impl directive");
+        assert_eq!(
+            help("#pragma cbundl impl=a.c"),
+            "expected '\"' before implementation file path"
+        );
+        assert_eq!(
+            help("#pragma cbundl impl \"a.c"),
+            "expected '\"' after implementation file path"
+        );
     }
 }
@@ -2,7 +2,9 @@ use std::path::PathBuf;
 
 use thiserror::Error;
 
-use super::*;
+use crate::diagnostic::Span;
+
+use super::Cursor;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum IncludeKind {
@@ -14,61 +16,75 @@ pub enum IncludeKind {
 pub struct Include {
     pub kind: IncludeKind,
     pub path: PathBuf,
+
+    /// Span of the include path (without the separators) inside the line.
+    pub path_span: Span,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
 pub enum ParseIncludeError {
     #[error("expected include path separator")]
-    MissingSeparator,
+    MissingSeparator { span: Span },
     #[error("invalid include path separator")]
-    InvalidSeparator,
+    InvalidSeparator { span: Span },
     #[error("missing include path")]
-    MissingPath,
+    MissingPath { span: Span },
+}
+
+impl ParseIncludeError {
+    /// Span of the error inside the line that was parsed.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::MissingSeparator { span }
+            | Self::InvalidSeparator { span }
+            | Self::MissingPath { span } => span.clone(),
+        }
+    }
+
+    pub fn help(&self) -> &'static str {
+        match self {
+            Self::MissingSeparator { .. } | Self::InvalidSeparator { .. } => {
+                "include paths must be surrounded by either '\"' or '<' and '>'"
+            }
+            Self::MissingPath { .. } => "the include path must not be empty",
+        }
+    }
 }
 
 impl Include {
     pub fn try_parse(s: &str) -> Option<Result<Self, ParseIncludeError>> {
-        let s = s.trim_start();
-        let s = s.strip_prefix("#include")?;
-        Some(parse_body(s))
+        let rest = s.trim_start().strip_prefix("#include")?;
+        Some(parse_body(Cursor::at(s, rest)))
     }
 }
 
-fn parse_body(s: &str) -> Result<Include, ParseIncludeError> {
-    let mut iter = s.chars().peekable();
-
-    consume_whitespace(&mut iter, false);
-    let sep = iter.next().ok_or(ParseIncludeError::MissingSeparator)?;
+fn parse_body(mut iter: Cursor<'_>) -> Result<Include, ParseIncludeError> {
+    iter.consume_whitespace();
 
-    match sep {
-        '"' => {
-            let path =
-                consume_while(&mut iter, |c| c != '"').ok_or(ParseIncludeError::MissingPath)?;
-
-            let sep = iter.next().ok_or(ParseIncludeError::MissingSeparator)?;
-            if sep != '"' {
-                return Err(ParseIncludeError::InvalidSeparator);
-            }
+    let span = iter.next_span();
+    let sep = iter
+        .next()
+        .ok_or(ParseIncludeError::MissingSeparator { span: span.clone() })?;
 
-            Ok(Include {
-                kind: IncludeKind::Local,
-                path: PathBuf::from(path),
-            })
-        }
-        '<' => {
-            let path =
-                consume_while(&mut iter, |c| c != '>').ok_or(ParseIncludeError::MissingPath)?;
+    let (kind, end) = match sep {
+        '"' => (IncludeKind::Local, '"'),
+        '<' => (IncludeKind::System, '>'),
+        _ => return Err(ParseIncludeError::InvalidSeparator { span }),
+    };
 
-            let sep = iter.next().ok_or(ParseIncludeError::MissingSeparator)?;
-            if sep != '>' {
-                return Err(ParseIncludeError::InvalidSeparator);
-            }
+    let path = iter
+        .consume_while(|c| c != end)
+        .ok_or_else(|| ParseIncludeError::MissingPath {
+            span: iter.next_span(),
+        })?;
 
-            Ok(Include {
-                kind: IncludeKind::System,
-                path: PathBuf::from(path),
-            })
-        }
-        _ => Err(ParseIncludeError::InvalidSeparator),
+    if iter.next() != Some(end) {
+        return Err(ParseIncludeError::MissingSeparator { span: iter.here() });
     }
+
+    Ok(Include {
+        kind,
+        path: PathBuf::from(path.value),
+        path_span: path.span,
+    })
 }
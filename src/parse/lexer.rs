@@ -0,0 +1,260 @@
+/// What the start of a physical line is part of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStart {
+    /// The line starts in live code.
+    Code,
+    /// The line starts inside a comment.
+    Comment,
+    /// The line starts inside a string or character literal.
+    Literal,
+    /// The line continues the previous one with a backslash-newline.
+    Continuation,
+    /// The line is inside a preprocessor group that is never compiled (`#if 0`).
+    Disabled,
+}
+
+impl LineStart {
+    /// Whether directives and includes on this line should be recognised.
+    pub fn is_live(self) -> bool {
+        self == Self::Code
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    LineComment,
+    BlockComment,
+    Literal(char),
+}
+
+/// State of a single `#if` group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Group {
+    /// The current branch is always compiled (`#if 1`).
+    Taken,
+    /// The current branch is never compiled (`#if 0`).
+    NotTaken,
+    /// Whether the current branch is compiled depends on the build.
+    Unknown,
+    /// A previous branch was always compiled, so no later one ever is.
+    Done,
+}
+
+/// A lightweight line-oriented C lexer.
+///
+/// The lexer is fed the source one physical line at a time and keeps track of
+/// comments, literals and line continuations that span multiple lines, as well
+/// as `#if 0` regions. It does not produce tokens, it only knows enough about C
+/// to tell whether a line starts in live code.
+#[derive(Debug, Clone)]
+pub struct Lexer {
+    state: State,
+    continued: bool,
+    groups: Vec<Group>,
+}
+
+impl Lexer {
+    pub fn new() -> Self {
+        Self {
+            state: State::Code,
+            continued: false,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Advance the lexer over `line` and report what the start of it was.
+    ///
+    /// `line` must not contain the trailing newline.
+    pub fn next_line(&mut self, line: &str) -> LineStart {
+        let start = match (self.continued, self.state) {
+            (true, _) => LineStart::Continuation,
+            (false, State::BlockComment | State::LineComment) => LineStart::Comment,
+            (false, State::Literal(_)) => LineStart::Literal,
+            (false, State::Code) if self.is_disabled() => LineStart::Disabled,
+            (false, State::Code) => LineStart::Code,
+        };
+
+        if matches!(start, LineStart::Code | LineStart::Disabled) {
+            self.track_conditional(line);
+        }
+
+        self.scan(line);
+
+        self.continued = line.ends_with('\\');
+        if !self.continued {
+            // Line comments and literals cannot span multiple lines without a
+            // continuation. Unterminated literals are an error in C, but that is
+            // the compiler's problem, not ours.
+            if matches!(self.state, State::LineComment | State::Literal(_)) {
+                self.state = State::Code;
+            }
+        }
+
+        start
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.groups
+            .iter()
+            .any(|x| matches!(x, Group::NotTaken | Group::Done))
+    }
+
+    fn scan(&mut self, line: &str) {
+        let mut iter = line.chars().peekable();
+
+        while let Some(c) = iter.next() {
+            match self.state {
+                State::Code => match c {
+                    '/' if iter.next_if_eq(&'/').is_some() => self.state = State::LineComment,
+                    '/' if iter.next_if_eq(&'*').is_some() => self.state = State::BlockComment,
+                    '"' | '\'' => self.state = State::Literal(c),
+                    _ => {}
+                },
+                State::LineComment => break,
+                State::BlockComment => {
+                    if c == '*' && iter.next_if_eq(&'/').is_some() {
+                        self.state = State::Code;
+                    }
+                }
+                State::Literal(quote) => {
+                    if c == '\\' {
+                        let _ = iter.next();
+                    } else if c == quote {
+                        self.state = State::Code;
+                    }
+                }
+            }
+        }
+    }
+
+    fn track_conditional(&mut self, line: &str) {
+        let Some(line) = line.trim_start().strip_prefix('#') else {
+            return;
+        };
+        let line = line.trim_start();
+
+        let keyword_len = line
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(line.len());
+        let (keyword, rest) = line.split_at(keyword_len);
+
+        let disabled = self.is_disabled();
+
+        match keyword {
+            "if" | "ifdef" | "ifndef" if disabled => self.groups.push(Group::Unknown),
+            "if" => self.groups.push(evaluate_condition(rest)),
+            "ifdef" | "ifndef" => self.groups.push(Group::Unknown),
+            "elif" => {
+                if let Some(group) = self.groups.last_mut() {
+                    *group = match *group {
+                        // Once a branch has been taken, no other branch can be.
+                        Group::Taken | Group::Done => Group::Done,
+                        Group::NotTaken | Group::Unknown => evaluate_condition(rest),
+                    };
+                }
+            }
+            "else" => {
+                if let Some(group) = self.groups.last_mut() {
+                    *group = match *group {
+                        Group::Taken | Group::Done => Group::Done,
+                        Group::NotTaken => Group::Taken,
+                        Group::Unknown => Group::Unknown,
+                    };
+                }
+            }
+            "endif" => {
+                let _ = self.groups.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn evaluate_condition(s: &str) -> Group {
+    let s = s.trim();
+    let s = match s.find("//").or_else(|| s.find("/*")) {
+        Some(i) => s[..i].trim_end(),
+        None => s,
+    };
+
+    match s {
+        "0" => Group::NotTaken,
+        "1" => Group::Taken,
+        _ => Group::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineStart::*;
+    use super::*;
+
+    fn starts(lines: &[&str]) -> Vec<LineStart> {
+        let mut lexer = Lexer::new();
+        lines.iter().map(|x| lexer.next_line(x)).collect()
+    }
+
+    #[test]
+    fn comments_and_literals() {
+        let lines = [
+            "int a; /* start",
+            "#include \"a.h\"",
+            "end */ int b;",
+            "// \\",
+            "#include \"b.h\"",
+            "const char *s = \"\\",
+            "#include \\\"c.h\\\"\";",
+            "int c; // \"/*\"",
+            "#include \"d.h\"",
+        ];
+
+        assert_eq!(
+            starts(&lines),
+            [
+                Code,
+                Comment,
+                Comment,
+                Code,
+                Continuation,
+                Code,
+                Continuation,
+                Code,
+                Code
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_groups() {
+        let lines = [
+            "#if 0",
+            "#include \"a.h\"",
+            "#if 1",
+            "#endif",
+            "#else",
+            "#include \"b.h\"",
+            "#endif",
+            "#if 1 // on",
+            "#elif X",
+            "#include \"c.h\"",
+            "#endif",
+            "#ifdef X",
+            "#include \"d.h\"",
+            "#endif",
+        ];
+
+        assert_eq!(
+            starts(&lines),
+            [
+                Code, Disabled, Disabled, Disabled, Disabled, Code, Code, Code, Code, Disabled,
+                Disabled, Code, Code, Code
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_literal_ends_with_the_line() {
+        assert_eq!(starts(&["char c = ';", "#include \"a.h\""]), [Code, Code]);
+    }
+}
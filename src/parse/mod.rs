@@ -1,55 +1,81 @@
-use std::iter::Peekable;
-use std::str::Chars;
+use crate::diagnostic::{Span, Spanned};
 
+pub mod declaration;
 pub mod directive;
 pub mod include;
+pub mod lexer;
 pub mod source_file;
+pub mod tokens;
 
-fn consume_whitespace(iter: &mut Peekable<Chars<'_>>, required: bool) -> Option<()> {
-    #[allow(clippy::collapsible_if)]
-    if required {
-        if !iter.next()?.is_whitespace() {
-            return None;
-        }
+/// A cursor over a line of text that keeps track of byte offsets so errors can
+/// point back at the exact place they occurred.
+#[derive(Debug, Clone)]
+struct Cursor<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Create a cursor over `s` starting at byte `pos`.
+    fn new(s: &'a str, pos: usize) -> Self {
+        Self { s, pos }
     }
 
-    while let Some(c) = iter.peek() {
-        if c.is_whitespace() {
-            let _ = iter.next();
-        } else {
-            break;
+    /// Create a cursor over `s` starting where `rest`, a suffix of `s`, begins.
+    fn at(s: &'a str, rest: &'a str) -> Self {
+        Self::new(s, s.len() - rest.len())
+    }
+
+    /// Get an empty span at the current position. Used for errors about
+    /// something that is missing.
+    fn here(&self) -> Span {
+        self.pos..self.pos
+    }
+
+    /// Get the span of the next character or an empty span at the end.
+    fn next_span(&self) -> Span {
+        let len = self.peek().map_or(0, char::len_utf8);
+        self.pos..self.pos + len
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn consume_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            let _ = self.next();
         }
     }
 
-    Some(())
-}
+    fn consume_while<F>(&mut self, mut f: F) -> Option<Spanned<&'a str>>
+    where
+        F: FnMut(char) -> bool,
+    {
+        let start = self.pos;
 
-fn consume_while<F>(iter: &mut Peekable<Chars<'_>>, mut f: F) -> Option<String>
-where
-    F: FnMut(char) -> bool,
-{
-    let mut buf = String::new();
+        while self.peek().is_some_and(&mut f) {
+            let _ = self.next();
+        }
 
-    while let Some(c) = iter.peek() {
-        if f(*c) {
-            buf.push(*c);
-            let _ = iter.next();
+        if start == self.pos {
+            None
         } else {
-            break;
+            Some(Spanned::new(&self.s[start..self.pos], start..self.pos))
         }
     }
 
-    if buf.is_empty() {
-        None
-    } else {
-        Some(buf)
+    fn consume_word(&mut self) -> Option<Spanned<&'a str>> {
+        self.consume_while(char::is_alphabetic)
     }
-}
 
-fn consume_word(iter: &mut Peekable<Chars<'_>>) -> Option<String> {
-    consume_while(iter, |c| c.is_alphabetic())
-}
-
-fn consume_until_whitespace(iter: &mut Peekable<Chars<'_>>) -> Option<String> {
-    consume_while(iter, |c| !c.is_whitespace())
+    fn consume_until_whitespace(&mut self) -> Option<Spanned<&'a str>> {
+        self.consume_while(|c| !c.is_whitespace())
+    }
 }
@@ -1,61 +1,163 @@
 use std::path::PathBuf;
 
-use eyre::{bail, Context, ContextCompat, Result};
+use thiserror::Error;
 
-use super::directive::Directive;
-use super::include::{Include, IncludeKind};
+use crate::diagnostic::{Span, Spanned};
+
+use super::directive::{Directive, ParseDirectiveError};
+use super::include::{Include, IncludeKind, ParseIncludeError};
+use super::lexer::Lexer;
 
 #[derive(Debug, Clone)]
 pub struct SourceFile {
     pub content: String,
-    pub impl_files: Vec<PathBuf>,
-    pub includes: Vec<PathBuf>,
+
+    /// The 1-based line number in the original file of each line in `content`.
+    pub line_numbers: Vec<usize>,
+
+    pub impl_files: Vec<Spanned<PathBuf>>,
+    pub includes: Vec<Spanned<PathBuf>>,
+
+    /// Bundled `<...>` includes. Unlike `includes`, these must only ever be
+    /// looked up in the system include paths.
+    pub system_includes: Vec<Spanned<PathBuf>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseSourceError {
+    #[error("{error}")]
+    Directive {
+        error: ParseDirectiveError,
+        line_start: usize,
+    },
+    #[error("{error}")]
+    Include {
+        error: ParseIncludeError,
+        line_start: usize,
+    },
+    #[error("missing include directive after bundle directive")]
+    MissingInclude { span: Span },
+}
+
+impl ParseSourceError {
+    /// Span of the error inside the source file.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Directive { error, line_start } => shift(error.span(), *line_start),
+            Self::Include { error, line_start } => shift(error.span(), *line_start),
+            Self::MissingInclude { span } => span.clone(),
+        }
+    }
+
+    pub fn help(&self) -> &'static str {
+        match self {
+            Self::Directive { error, .. } => error.help(),
+            Self::Include { error, .. } => error.help(),
+            Self::MissingInclude { .. } => {
+                "a bundle directive must be immediately followed by an '#include'"
+            }
+        }
+    }
 }
 
 impl SourceFile {
-    pub fn try_parse(s: &str) -> Result<Self> {
+    /// Parse `s` and recover from errors so that all of them can be reported.
+    ///
+    /// Lines that fail to parse are left out of the result.
+    pub fn parse(s: &str) -> (Self, Vec<ParseSourceError>) {
         let mut content = String::with_capacity(s.len());
+        let mut line_numbers = Vec::new();
         let mut includes = Vec::new();
+        let mut system_includes = Vec::new();
         let mut impl_files = Vec::new();
+        let mut errors = Vec::new();
 
-        let mut lines = s.lines().enumerate().peekable();
+        let mut lexer = Lexer::new();
+        let mut lines = lines_with_offsets(s)
+            .enumerate()
+            .map(|(line_idx, (line_start, line))| {
+                let is_live = lexer.next_line(line).is_live();
+                (line_idx, line_start, line, is_live)
+            })
+            .peekable();
 
-        while let Some((line_no, line)) = lines.next() {
-            match Directive::try_parse(line) {
+        while let Some((line_idx, line_start, line, is_live)) = lines.next() {
+            let directive = if is_live {
+                Directive::try_parse(line)
+            } else {
+                None
+            };
+
+            match directive {
                 Some(Ok(Directive::Bundle)) => {
-                    let include = lines
-                        .next()
-                        .and_then(|(_, next_line)| Include::try_parse(next_line))
-                        .context("missing include directive after bundle directive")?
-                        .with_context(|| {
-                            format!("failed to parse include directive at line {}", line_no + 1)
-                        })?;
-
-                    if include.kind != IncludeKind::Local {
-                        bail!("cbundl supports only local includes");
-                    }
+                    let next = lines.next_if(|(_, _, next_line, is_live)| {
+                        *is_live && Include::try_parse(next_line).is_some()
+                    });
+
+                    let Some((_, next_line_start, next_line, _)) = next else {
+                        errors.push(ParseSourceError::MissingInclude {
+                            span: shift(0..line.len(), line_start),
+                        });
+                        continue;
+                    };
+
+                    let include = match Include::try_parse(next_line) {
+                        Some(Ok(x)) => x,
+                        Some(Err(error)) => {
+                            errors.push(ParseSourceError::Include {
+                                error,
+                                line_start: next_line_start,
+                            });
+                            continue;
+                        }
+                        None => unreachable!("line was checked to be an include"),
+                    };
 
-                    includes.push(include.path);
+                    let span = shift(include.path_span, next_line_start);
+
+                    let include_path = Spanned::new(include.path, span);
+                    match include.kind {
+                        IncludeKind::Local => includes.push(include_path),
+                        IncludeKind::System => system_includes.push(include_path),
+                    }
                 }
                 Some(Ok(Directive::ImplFile(impl_file_path))) => {
-                    impl_files.push(impl_file_path);
-                }
-                Some(Err(e)) => {
-                    return Err(e).with_context(|| {
-                        format!("failed to parse directive at line {}", line_no + 1)
-                    })
+                    let span = shift(impl_file_path.span, line_start);
+                    impl_files.push(Spanned::new(impl_file_path.value, span));
                 }
+                Some(Err(error)) => errors.push(ParseSourceError::Directive { error, line_start }),
                 None => {
                     content.push_str(line);
                     content.push('\n');
+                    line_numbers.push(line_idx + 1);
                 }
             }
         }
 
-        Ok(Self {
+        let this = Self {
             content,
+            line_numbers,
             impl_files,
             includes,
-        })
+            system_includes,
+        };
+
+        (this, errors)
     }
 }
+
+/// Like [`str::lines`] but also yields the byte offset at which each line starts.
+pub(super) fn lines_with_offsets(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    s.split_inclusive('\n').scan(0, |offset, line| {
+        let line_start = *offset;
+        *offset += line.len();
+
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        Some((line_start, line))
+    })
+}
+
+fn shift(span: Span, by: usize) -> Span {
+    span.start + by..span.end + by
+}
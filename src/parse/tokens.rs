@@ -0,0 +1,274 @@
+use crate::diagnostic::Span;
+
+use super::lexer::{Lexer, LineStart};
+use super::source_file::lines_with_offsets;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    Punct,
+    /// Numbers, strings and character literals.
+    Literal,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+
+    /// The token is part of a preprocessor directive.
+    pub directive: bool,
+
+    /// Index into [`Tokens::conditions`] of the preprocessor conditions the
+    /// token is compiled under.
+    pub condition: usize,
+}
+
+/// The tokens of a C source file.
+///
+/// This is not a real preprocessor. Macros are not expanded and conditional
+/// groups are not evaluated, apart from `#if 0`, whose contents are left out.
+/// Instead, each token remembers the conditions it is compiled under, so that
+/// code from mutually exclusive groups can be told apart. Include guards are
+/// not counted as conditions. `#include` directives are left out too.
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+    pub text: &'a str,
+    pub tokens: Vec<Token>,
+
+    /// Every distinct set of conditions. The first one is empty and belongs
+    /// to code that is always compiled.
+    pub conditions: Vec<Vec<String>>,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let mut tokenizer = Tokenizer {
+            tokens: Vec::new(),
+            conditions: vec![Vec::new()],
+            stack: Vec::new(),
+            guard: Guard::Unknown,
+            in_comment: false,
+        };
+
+        let mut lexer = Lexer::new();
+        let mut in_directive = false;
+
+        for (line_start, line) in lines_with_offsets(text) {
+            let start = lexer.next_line(line);
+
+            let directive = match start {
+                LineStart::Code | LineStart::Disabled => line.trim_start().starts_with('#'),
+                LineStart::Continuation => in_directive,
+                LineStart::Comment | LineStart::Literal => false,
+            };
+
+            if directive && start != LineStart::Continuation {
+                tokenizer.directive(line);
+            }
+
+            in_directive = directive && line.ends_with('\\');
+
+            if start != LineStart::Disabled && !is_include(line, directive) {
+                tokenizer.line(line, line_start, directive);
+            }
+        }
+
+        Self {
+            text,
+            tokens: tokenizer.tokens,
+            conditions: tokenizer.conditions,
+        }
+    }
+
+    pub fn text(&self, token: &Token) -> &'a str {
+        &self.text[token.span.clone()]
+    }
+
+    /// Whether the token at `i` is `s`.
+    pub fn is(&self, i: usize, s: &str) -> bool {
+        self.tokens.get(i).is_some_and(|x| self.text(x) == s)
+    }
+}
+
+fn is_include(line: &str, directive: bool) -> bool {
+    directive
+        && line
+            .trim_start()
+            .strip_prefix('#')
+            .is_some_and(|x| x.trim_start().starts_with("include"))
+}
+
+/// Whether the file has an include guard, as far as it has been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Guard {
+    /// Nothing but comments have been read so far.
+    Unknown,
+    /// The first directive was `#ifndef`, the next one must be the `#define`.
+    Pending,
+    /// The outermost conditional group is an include guard or there is no
+    /// include guard.
+    Decided,
+}
+
+struct Tokenizer {
+    tokens: Vec<Token>,
+    conditions: Vec<Vec<String>>,
+
+    /// The conditions of the groups that are open. `None` is an include
+    /// guard.
+    stack: Vec<Option<String>>,
+    guard: Guard,
+
+    in_comment: bool,
+}
+
+impl Tokenizer {
+    fn condition(&mut self) -> usize {
+        let condition: Vec<String> = self.stack.iter().flatten().cloned().collect();
+
+        match self.conditions.iter().position(|x| *x == condition) {
+            Some(x) => x,
+            None => {
+                self.conditions.push(condition);
+                self.conditions.len() - 1
+            }
+        }
+    }
+
+    /// Keep track of the conditional groups. Only the first line of the
+    /// directive is needed.
+    fn directive(&mut self, line: &str) {
+        let line = line.trim_start().trim_start_matches('#').trim_start();
+        let line = line.trim_end_matches('\\');
+        let line = match line.find("//").or_else(|| line.find("/*")) {
+            Some(i) => &line[..i],
+            None => line,
+        };
+
+        let keyword_len = line
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(line.len());
+        let (keyword, rest) = line.split_at(keyword_len);
+        let rest = rest.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let guard = self.guard;
+        if keyword != "pragma" {
+            self.guard = Guard::Decided;
+        }
+
+        match keyword {
+            "ifndef" if guard == Guard::Unknown && self.stack.is_empty() => {
+                self.stack.push(Some(format!("!defined({rest})")));
+                self.guard = Guard::Pending;
+            }
+            "define" if guard == Guard::Pending => {
+                let name = rest.split(|c: char| !c.is_ascii_alphanumeric() && c != '_');
+                let expected = format!("!defined({})", name.clone().next().unwrap_or_default());
+
+                if self.stack.last().and_then(Option::as_ref) == Some(&expected) {
+                    self.stack.pop();
+                    self.stack.push(None);
+                }
+            }
+            "if" => self.stack.push(Some(rest)),
+            "ifdef" => self.stack.push(Some(format!("defined({rest})"))),
+            "ifndef" => self.stack.push(Some(format!("!defined({rest})"))),
+            "elif" | "elifdef" | "elifndef" => {
+                if let Some(Some(x)) = self.stack.last_mut() {
+                    *x = format!("{x} #{keyword} {rest}");
+                }
+            }
+            "else" => {
+                if let Some(Some(x)) = self.stack.last_mut() {
+                    *x = format!("{x} #else");
+                }
+            }
+            "endif" => {
+                let _ = self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn line(&mut self, line: &str, line_start: usize, directive: bool) {
+        let condition = self.condition();
+        let bytes = line.as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if self.in_comment {
+                match line[i..].find("*/") {
+                    Some(x) => {
+                        i += x + 2;
+                        self.in_comment = false;
+                    }
+                    None => return,
+                }
+                continue;
+            }
+
+            let c = bytes[i];
+            let start = i;
+
+            let kind = match c {
+                b'/' if bytes.get(i + 1) == Some(&b'/') => return,
+                b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                    self.in_comment = true;
+                    i += 2;
+                    continue;
+                }
+                _ if c.is_ascii_whitespace() || c == b'\\' => {
+                    i += 1;
+                    continue;
+                }
+                b'"' | b'\'' => {
+                    i += 1;
+                    while i < bytes.len() && bytes[i] != c {
+                        i += if bytes[i] == b'\\' { 2 } else { 1 };
+                    }
+                    i = (i + 1).min(bytes.len());
+                    TokenKind::Literal
+                }
+                _ if c.is_ascii_digit()
+                    || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) =>
+                {
+                    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.')
+                    {
+                        i += 1;
+                    }
+                    TokenKind::Literal
+                }
+                _ if c.is_ascii_alphabetic() || c == b'_' || !c.is_ascii() => {
+                    while i < bytes.len()
+                        && (bytes[i].is_ascii_alphanumeric()
+                            || bytes[i] == b'_'
+                            || !bytes[i].is_ascii())
+                    {
+                        i += 1;
+                    }
+                    TokenKind::Ident
+                }
+                b'-' if bytes.get(i + 1) == Some(&b'>') => {
+                    i += 2;
+                    TokenKind::Punct
+                }
+                _ => {
+                    i += 1;
+                    TokenKind::Punct
+                }
+            };
+
+            if !directive {
+                self.guard = Guard::Decided;
+            }
+
+            self.tokens.push(Token {
+                kind,
+                span: line_start + start..line_start + i,
+                directive,
+                condition,
+            });
+        }
+    }
+}
@@ -1,10 +1,13 @@
-use eyre::{Context, Result};
+use std::path::PathBuf;
 
 use crate::banner::Banner;
 use crate::bundler::Bundler;
+use crate::error::{Error, Result};
 use crate::formatter::Formatter;
 use crate::header::Header;
+use crate::line_markers;
 use crate::source::Sources;
+use crate::source_map::SourceMap;
 
 pub trait Stage: Sized {
     fn name() -> &'static str;
@@ -30,22 +33,85 @@ pub struct Pipeline {
     pub header: Option<Header>,
     pub banner: Option<Banner>,
     pub formatter: Option<Formatter>,
+
+    /// Build a source map for the bundle. Requires the bundler and the
+    /// formatter to emit line markers.
+    pub source_map: bool,
+
+    /// Remove the line markers from the final bundle once the source map has
+    /// been built. They are only needed to build it.
+    pub strip_line_markers: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub code: String,
+    pub source_map: Option<SourceMap>,
+
+    /// The header of an amalgamation. `code` includes it.
+    pub public_header: Option<PublicHeader>,
+
+    /// Every file the bundle was made from. This is all the source files and
+    /// the header file, if there is one. The paths are canonical, so the same
+    /// file is never listed twice.
+    pub dependencies: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicHeader {
+    pub code: String,
+    pub source_map: Option<SourceMap>,
 }
 
 impl Pipeline {
-    pub fn process(&mut self, sources: &Sources) -> Result<String> {
-        let mut out = self.bundler.bundle(sources);
+    pub fn process(&mut self, sources: &Sources) -> Result<Bundle> {
+        let (code, source_map) = self.finish(self.bundler.bundle(sources))?;
+
+        let public_header = match self.bundler.bundle_header(sources) {
+            Some(x) => {
+                let (code, source_map) = self.finish(x)?;
+                Some(PublicHeader { code, source_map })
+            }
+            None => None,
+        };
+
+        let dependencies = sources.files().map(|x| x.real_path.clone()).collect();
 
+        Ok(Bundle {
+            code,
+            source_map,
+            public_header,
+            dependencies,
+        })
+    }
+
+    /// Run the stages after the bundler on `out` and build its source map.
+    fn finish(&mut self, mut out: String) -> Result<(String, Option<SourceMap>)> {
         out = run_stage(&mut self.banner, out)?;
         out = run_stage(&mut self.header, out)?;
         out = run_stage(&mut self.formatter, out)?;
 
-        Ok(out)
+        let source_map = if self.source_map {
+            let locations = if self.strip_line_markers {
+                let (stripped, locations) = line_markers::strip(&out);
+                out = stripped;
+                locations
+            } else {
+                line_markers::locations(&out)
+            };
+
+            Some(SourceMap::new(&locations))
+        } else {
+            None
+        };
+
+        Ok((out, source_map))
     }
 }
 
 fn run_stage<S: Stage>(stage: &mut S, code: String) -> Result<String> {
-    stage
-        .process(code)
-        .with_context(|| format!("stage '{}' failed", S::name()))
+    stage.process(code).map_err(|e| Error::Stage {
+        stage: S::name(),
+        source: Box::new(e),
+    })
 }
@@ -2,7 +2,7 @@ use std::marker::PhantomData;
 use std::vec::IntoIter as VecIter;
 
 use rand::seq::IteratorRandom;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone)]
 enum QuoteInner<'a> {
@@ -62,7 +62,7 @@ impl<'a> Iterator for QuoteLinesIter<'a> {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum QuotePicker {
     All,
@@ -70,7 +70,7 @@ pub enum QuotePicker {
     Builtin,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CustomQuote {
     pub text: String,
     pub author: String,
@@ -0,0 +1,128 @@
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use serde::Serialize;
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Origin {
+    /// The built-in default.
+    Default,
+
+    /// A command line argument.
+    Cli { arg: String },
+
+    /// An environment variable.
+    Env { var: String },
+
+    /// A configuration file. `section` is the profile or target that
+    /// overrode the rest of the file, like `[profile.debug]`.
+    File {
+        path: PathBuf,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        section: Option<String>,
+    },
+
+    /// More than one place, in order of priority. Only used for lists that
+    /// are joined together.
+    Combined { origins: Vec<Origin> },
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::Cli { arg } => write!(f, "command line `{arg}`"),
+            Self::Env { var } => write!(f, "environment variable `{var}`"),
+            Self::File {
+                path,
+                section: None,
+            } => write!(f, "config file `{}`", path.display()),
+            Self::File {
+                path,
+                section: Some(section),
+            } => write!(f, "config file `{}` in `{section}`", path.display()),
+            Self::Combined { origins } => {
+                for (i, x) in origins.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", then ")?;
+                    }
+
+                    x.fmt(f)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// One setting of the effective configuration.
+#[derive(Debug, Clone, Serialize)]
+pub struct Setting {
+    /// The dotted path of the setting, like the keys of the configuration
+    /// file.
+    pub key: &'static str,
+    pub value: serde_json::Value,
+    pub origin: Origin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SettingsFormat {
+    Toml,
+    Json,
+}
+
+pub fn render(settings: &[Setting], format: SettingsFormat) -> Result<String> {
+    match format {
+        SettingsFormat::Toml => render_toml(settings),
+        SettingsFormat::Json => {
+            let mut out =
+                serde_json::to_string_pretty(settings).context("failed to serialize settings")?;
+            out.push('\n');
+            Ok(out)
+        }
+    }
+}
+
+/// Render `settings` as a TOML document with the origin of each one in a
+/// comment next to it. Settings that are not set are commented out.
+fn render_toml(settings: &[Setting]) -> Result<String> {
+    let split = |key: &'static str| key.rsplit_once('.').unwrap_or(("", key));
+
+    // Keys outside of any table must come before the first table.
+    let mut tables: Vec<&str> = Vec::new();
+    for x in settings {
+        let (table, _) = split(x.key);
+        if !tables.contains(&table) {
+            tables.push(table);
+        }
+    }
+    tables.sort_by_key(|x| !x.is_empty());
+
+    let mut out = String::new();
+    for table in tables {
+        if !table.is_empty() {
+            writeln!(out, "\n[{table}]")?;
+        }
+
+        for setting in settings.iter().filter(|x| split(x.key).0 == table) {
+            let (_, key) = split(setting.key);
+
+            if setting.value.is_null() {
+                writeln!(out, "# {key} is not set ({})", setting.origin)?;
+                continue;
+            }
+
+            let value = toml::Value::try_from(&setting.value)
+                .with_context(|| format!("failed to serialize `{}`", setting.key))?;
+
+            writeln!(out, "{key} = {value} # {}", setting.origin)?;
+        }
+    }
+
+    Ok(out)
+}
@@ -1,22 +1,38 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::fs;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
 use std::iter::{Chain, FusedIterator};
 use std::path::{Path, PathBuf};
 use std::slice;
+use std::sync::Arc;
 
-use eyre::{bail, Context, Result};
-use petgraph::algo::toposort;
+use petgraph::algo::{has_path_connecting, is_cyclic_directed, tarjan_scc, toposort};
+use petgraph::graph::EdgeReference;
+use petgraph::visit::{
+    Dfs, EdgeFiltered, EdgeRef, IntoEdgeReferences, IntoEdges, IntoNeighbors, IntoNodeIdentifiers,
+    NodeIndexable,
+};
 
+use crate::diagnostic::{Diagnostic, Diagnostics, SourceText, Span, DIAGNOSTIC_LOG_TARGET};
 use crate::display::display_path;
 use crate::parse::source_file::SourceFile;
+use crate::parse::tokens::Tokens;
+use crate::symbols;
+use crate::vfs::FileSystem;
 
-type Graph = petgraph::Graph<Source, (), petgraph::Directed, u32>;
+type Graph = petgraph::Graph<Source, Dependency, petgraph::Directed, u32>;
 type NodeIndex = petgraph::graph::NodeIndex<u32>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which source files must come before which in the bundle. It has the same
+/// nodes as the source graph. Edges point from a file to one that must come
+/// before it.
+type Constraints = petgraph::Graph<(), Constraint, petgraph::Directed, u32>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum SourceKind {
     Declaration,
+    #[default]
     Implementation,
 }
 
@@ -24,151 +40,452 @@ pub enum SourceKind {
 pub struct Source {
     pub kind: SourceKind,
     pub path: PathBuf,
+
+    /// Canonical path of the source file, as given by the filesystem it was
+    /// read from.
+    pub real_path: PathBuf,
+
+    /// Path of the source file relative to the deepest directory that contains
+    /// all source files.
+    pub relative_path: PathBuf,
+
+    /// The source file exactly as it was read.
+    pub original: SourceText,
+
     pub content: String,
+
+    /// The 1-based line number in the original file of each line in `content`.
+    pub line_numbers: Vec<usize>,
+
+    /// The source file is part of the public interface. This is a public
+    /// header or a declaration file that one of them includes.
+    pub public: bool,
+
+    /// The `static` symbols that were renamed in `content`, as pairs of the
+    /// old and the new name.
+    pub renamed: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Sources {
     graph: Graph,
+    entry: NodeIndex,
     dependencies: Vec<NodeIndex>,
 }
 
-struct SourceGraphBuilder {
+#[derive(Debug, Clone, Default)]
+pub struct SourcesOptions {
+    /// Keep walking the source graph after an error and report every error at
+    /// the end instead of stopping at the first one.
+    pub keep_going: bool,
+
+    /// Directories to search for bundled includes that are not found next to
+    /// the file that includes them. Searched in order, like `-I` for a C
+    /// compiler.
+    pub include_paths: Vec<PathBuf>,
+
+    /// Directories to search for bundled `<...>` includes. These are the only
+    /// places such includes are looked up, so real system headers are never
+    /// bundled.
+    pub system_include_paths: Vec<PathBuf>,
+
+    /// The kind of the entry file. This is a declaration when the entry is
+    /// the public header of a library.
+    pub entry_kind: SourceKind,
+
+    /// The headers that make up the public interface. Each of them must be
+    /// part of the source graph. See [`Source::public`].
+    pub public_headers: Vec<PathBuf>,
+
+    /// Rename `static` symbols that more than one implementation file
+    /// defines instead of reporting them.
+    pub rename_statics: bool,
+}
+
+struct SourceGraphBuilder<'a, F: ?Sized> {
+    fs: &'a F,
     graph: Graph,
     files: HashMap<PathBuf, NodeIndex>,
+    failed: HashSet<PathBuf>,
+    keep_going: bool,
+    include_paths: Vec<PathBuf>,
+    system_include_paths: Vec<PathBuf>,
+    errors: Vec<Diagnostic>,
 }
 
-impl SourceGraphBuilder {
-    fn add_source_file(&mut self, path: PathBuf, kind: SourceKind) -> Result<NodeIndex> {
-        fn realpath(path: &Path) -> Result<PathBuf> {
-            fs::canonicalize(path)
-                .with_context(|| format!("failed to resolve path `{}`", display_path(path)))
+/// The way one source file refers to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    /// A bundled `#include "..."`.
+    Include,
+    /// A bundled `#include <...>`.
+    SystemInclude,
+    /// An `impl` directive.
+    ImplFile,
+}
+
+impl Reference {
+    fn kind(self) -> SourceKind {
+        match self {
+            Self::Include | Self::SystemInclude => SourceKind::Declaration,
+            Self::ImplFile => SourceKind::Implementation,
         }
+    }
+}
 
-        let raw_content = fs::read_to_string(&path)
-            .with_context(|| format!("failed to read source file `{}`", display_path(&path)))?;
+/// An edge of the source graph. It points from the file that contains the
+/// reference to the file being referenced.
+#[derive(Debug, Clone)]
+struct Dependency {
+    reference: Reference,
 
-        let SourceFile {
-            content,
-            impl_files,
-            includes,
-        } = SourceFile::try_parse(&raw_content)
-            .with_context(|| format!("failed to parse file `{}`", display_path(&path)))?;
+    /// Span of the referenced path inside the referencing file.
+    span: Span,
+}
+
+/// The reason a source file must come after another one.
+#[derive(Debug, Clone)]
+enum Constraint {
+    /// The file includes the other one.
+    Include(Span),
+
+    /// The file uses a symbol that the other one defines.
+    Symbol { symbol: String, span: Span },
+}
+
+/// The place a source file was referenced from.
+#[derive(Debug, Clone, Copy)]
+struct Origin<'a> {
+    source: &'a SourceText,
+    span: &'a Span,
+}
+
+impl Origin<'_> {
+    fn attach(self, diagnostic: Diagnostic) -> Diagnostic {
+        diagnostic.with_label(self.source, self.span.clone(), "referenced here")
+    }
+}
+
+fn attach_origin(diagnostic: Diagnostic, origin: Option<Origin<'_>>) -> Diagnostic {
+    match origin {
+        Some(origin) => origin.attach(diagnostic),
+        None => diagnostic,
+    }
+}
+
+impl<F: FileSystem + ?Sized> SourceGraphBuilder<'_, F> {
+    fn realpath(&self, path: &Path, origin: Option<Origin<'_>>) -> Result<PathBuf, Diagnostic> {
+        self.fs.canonicalize(path).map_err(|e| {
+            let diagnostic =
+                Diagnostic::error(format!("failed to resolve path `{}`", display_path(path)))
+                    .with_note(e.to_string());
+
+            attach_origin(diagnostic, origin)
+        })
+    }
+
+    /// Find the file that `path` refers to.
+    ///
+    /// Includes and implementation files are first looked up relative to
+    /// `base`, the directory of the file that references them. Includes are
+    /// then looked up in each of the include paths. System includes are only
+    /// ever looked up in the system include paths.
+    fn resolve(
+        &self,
+        base: &Path,
+        path: &Path,
+        reference: Reference,
+        origin: Origin<'_>,
+    ) -> Result<PathBuf, Diagnostic> {
+        let (local, search) = match reference {
+            Reference::Include => (Some(base), self.include_paths.as_slice()),
+            Reference::SystemInclude => (None, self.system_include_paths.as_slice()),
+            Reference::ImplFile => (Some(base), &[][..]),
+        };
+
+        if reference == Reference::SystemInclude && search.is_empty() {
+            return Err(origin.attach(
+                Diagnostic::error("cannot bundle a system include")
+                    .with_note("no `system_include_paths` are configured")
+                    .with_help(
+                        "use '\"' instead of '<' and '>' for the include path, or add the directory that contains it to `system_include_paths`",
+                    ),
+            ));
+        }
+
+        if let Some(base) = local {
+            let local = base.join(path);
+
+            if search.is_empty() || self.fs.exists(&local) {
+                return self.realpath(&local, Some(origin));
+            }
+        }
+
+        match search
+            .iter()
+            .map(|x| x.join(path))
+            .find(|x| self.fs.exists(x))
+        {
+            Some(x) => self.realpath(&x, Some(origin)),
+            None => {
+                let searched = chain(local.into_iter(), search.iter().map(PathBuf::as_path))
+                    .map(|x| format!("`{}`", display_path(x)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                Err(origin.attach(
+                    Diagnostic::error(format!("failed to find `{}`", display_path(path)))
+                        .with_note(format!("searched in: {searched}")),
+                ))
+            }
+        }
+    }
+
+    /// Find the public header at `path` in the graph.
+    fn public_header(&self, path: &Path) -> Result<NodeIndex, Diagnostic> {
+        let real_path = self.realpath(path, None)?;
+
+        let Some(idx) = self.files.get(&real_path).copied() else {
+            return Err(Diagnostic::error(format!(
+                "public header `{}` is not part of the bundle",
+                display_path(path)
+            ))
+            .with_help("public headers must be included from the entry, directly or not"));
+        };
+
+        if self.graph[idx].kind != SourceKind::Declaration {
+            return Err(Diagnostic::error(format!(
+                "public header `{}` is an implementation file",
+                display_path(path)
+            )));
+        }
+
+        Ok(idx)
+    }
+
+    /// Record an error. If the builder should not keep going, the error is
+    /// returned back so it can be propagated with `?`.
+    fn error(&mut self, diagnostic: Diagnostic) -> Result<(), Diagnostic> {
+        if self.keep_going {
+            self.errors.push(diagnostic);
+            Ok(())
+        } else {
+            Err(diagnostic)
+        }
+    }
+
+    /// Add the source file at `path` to the graph. Returns `None` if the file
+    /// could not be added because of an error that has already been recorded.
+    fn add_source_file(
+        &mut self,
+        path: PathBuf,
+        kind: SourceKind,
+        origin: Option<Origin<'_>>,
+    ) -> Result<Option<NodeIndex>, Diagnostic> {
+        let raw_content = match self.fs.read_to_string(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                let diagnostic = Diagnostic::error(format!(
+                    "failed to read source file `{}`",
+                    display_path(&path)
+                ))
+                .with_note(e.to_string());
+
+                self.error(attach_origin(diagnostic, origin))?;
+                return Ok(None);
+            }
+        };
+
+        let source = SourceText::new(path.clone(), raw_content);
+
+        let (
+            SourceFile {
+                content,
+                line_numbers,
+                impl_files,
+                includes,
+                system_includes,
+            },
+            errors,
+        ) = SourceFile::parse(&source.text);
+
+        for e in errors {
+            self.error(
+                Diagnostic::error(e.to_string())
+                    .with_span(&source, e.span())
+                    .with_help(e.help()),
+            )?;
+        }
 
         let base = match path.parent() {
             None => PathBuf::from("."),
             Some(x) => x.to_path_buf(),
         };
 
-        let real_path = realpath(&path)?;
+        let real_path = match self.realpath(&path, origin) {
+            Ok(x) => x,
+            Err(e) => {
+                self.error(e)?;
+                return Ok(None);
+            }
+        };
+
         let me = self.graph.add_node(Source {
             kind,
             path,
+            real_path: real_path.clone(),
+            relative_path: PathBuf::new(),
+            original: source.clone(),
             content,
+            line_numbers,
+            public: false,
+            renamed: Vec::new(),
         });
 
         self.files.insert(real_path, me);
 
-        let includes = includes.into_iter().map(|x| (SourceKind::Declaration, x));
+        let includes = includes.into_iter().map(|x| (Reference::Include, x));
 
-        let impl_files = impl_files
+        let system_includes = system_includes
             .into_iter()
-            .map(|x| (SourceKind::Implementation, x));
+            .map(|x| (Reference::SystemInclude, x));
 
-        let related_files = chain(includes, impl_files)
-            .map(|(kind, x)| (kind, base.join(x)))
-            .map(|(kind, x)| realpath(&x).map(|x| (kind, x)));
+        let impl_files = impl_files.into_iter().map(|x| (Reference::ImplFile, x));
 
-        for x in related_files {
-            let (kind, path) = x?;
+        for (reference, x) in chain(chain(includes, system_includes), impl_files) {
+            let kind = reference.kind();
+            let origin = Origin {
+                source: &source,
+                span: &x.span,
+            };
 
-            let other = match self.files.get(&path) {
-                Some(x) => *x,
-                None => {
-                    let other = self.add_source_file(path.clone(), kind)?;
-                    self.files.insert(path, other);
-                    other
+            let path = match self.resolve(&base, &x.value, reference, origin) {
+                Ok(x) => x,
+                Err(e) => {
+                    self.error(e)?;
+                    continue;
                 }
             };
 
-            #[allow(clippy::single_match)]
-            match (self.graph[me].kind, self.graph[other].kind) {
-                // If a declaration file includes another declaration file, then we track
-                // this relation to detect cyclic dependencies later.
-                (SourceKind::Declaration, SourceKind::Declaration) => {
-                    self.graph.add_edge(me, other, ());
-                }
-                // However, if an implementation file includes a declaration file, then we
-                // will not keep track of this relation in order not to mess up the dependency
-                // finding algorithm later. Basically if we were to track this relation,
-                // then the following would not work:
-                //
-                // a.h:
-                // // cbundl: bundle
-                // #include "b.h"
-                //
-                // void a();
-                //
-                // // cbundl: impl=a.c
-                //
-                // a.c:
-                // // cbundl: bundle
-                // #include "b.h"
-                //
-                // void a() { b(); }
-                //
-                // b.h:
-                // // cbundl: bundle
-                // #include "a.h"
-                //
-                // void b();
-                //
-                // // cbundl: impl=b.c
-                //
-                // b.c:
-                // // cbundl: bundle
-                // #include "a.h"
-                //
-                // void b() { a(); }
-                //
-                // main.c:
-                // // cbundl: bundle
-                // #include "a.h"
-                // // cbundl: bundle
-                // #include "b.h"
-                //
-                // int main() { /* ... */ }
-                //
-                // But the above example compiles successfully because even though a circlular
-                // dependency exists, the header files contain only function declarations,
-                // and thus it is perfectly fine if the implementation files include them
-                // in this manner. This algorithm, does run the risk of accepting invalid
-                // code and creating bundles out of them. To fix this we would have to track
-                // symbol dependencies (not file dependencies). Also, it is not our job to
-                // perform strict checking of the code. It is then the responsibility of the
-                // compiler to reject invalid code.
-                _ => {}
+            if self.failed.contains(&path) {
+                continue;
             }
+
+            let other = match self.files.get(&path) {
+                Some(x) => *x,
+                None => match self.add_source_file(path.clone(), kind, Some(origin))? {
+                    Some(other) => {
+                        self.files.insert(path, other);
+                        other
+                    }
+                    None => {
+                        self.failed.insert(path);
+                        continue;
+                    }
+                },
+            };
+
+            self.graph.add_edge(
+                me,
+                other,
+                Dependency {
+                    reference,
+                    span: x.span.clone(),
+                },
+            );
         }
 
-        Ok(me)
+        Ok(Some(me))
     }
 }
 
 impl Sources {
-    pub fn new(entry: PathBuf) -> Result<Self> {
+    /// Load the source file at `entry` and everything it references from `fs`.
+    pub fn new<F>(fs: &F, entry: PathBuf, options: &SourcesOptions) -> Result<Self, Diagnostics>
+    where
+        F: FileSystem + ?Sized,
+    {
         let mut builder = SourceGraphBuilder {
+            fs,
             graph: Graph::new(),
             files: HashMap::new(),
+            failed: HashSet::new(),
+            keep_going: options.keep_going,
+            include_paths: options.include_paths.clone(),
+            system_include_paths: options.system_include_paths.clone(),
+            errors: Vec::new(),
+        };
+
+        let entry = builder.add_source_file(entry, options.entry_kind, None)?;
+
+        let mut public_headers = Vec::with_capacity(options.public_headers.len());
+        for path in &options.public_headers {
+            match builder.public_header(path) {
+                Ok(x) => public_headers.push(x),
+                Err(e) => builder.error(e)?,
+            }
+        }
+
+        let SourceGraphBuilder {
+            mut graph,
+            files,
+            errors,
+            ..
+        } = builder;
+
+        let entry = match entry {
+            Some(x) if errors.is_empty() => x,
+            _ => return Err(Diagnostics::new(errors)),
         };
 
-        let entry = builder.add_source_file(entry, SourceKind::Implementation)?;
+        let root = common_root(files.keys().filter_map(|x| x.parent()));
+        for (real_path, idx) in &files {
+            graph[*idx].relative_path = real_path
+                .strip_prefix(&root)
+                .unwrap_or(real_path)
+                .to_path_buf();
+        }
+
+        // Check the symbols only now that every file is known, as it takes
+        // the whole bundle to know which ones collide. All the checks look at
+        // the original text of the files, so it is only tokenized once.
+        let texts: Vec<Arc<str>> = graph
+            .node_weights()
+            .map(|x| x.original.text.clone())
+            .collect();
+        let tokens: Vec<Tokens<'_>> = texts.iter().map(|x| Tokens::new(x)).collect();
+
+        let mut errors = symbols::check_statics(
+            &mut graph.node_weights_mut().collect::<Vec<_>>(),
+            &tokens,
+            options.rename_statics,
+        );
+        errors.extend(symbols::check_externals(
+            &graph.node_weights().collect::<Vec<_>>(),
+            &tokens,
+        ));
+        if !errors.is_empty() {
+            return Err(Diagnostics::new(errors));
+        }
+
+        // Everything a public header includes must be public too, otherwise
+        // the public interface would be incomplete.
+        let mut public = Vec::new();
+        let mut dfs = Dfs::empty(&graph);
+        for x in public_headers {
+            dfs.move_to(x);
+            while let Some(x) = dfs.next(&ordering(&graph)) {
+                public.push(x);
+            }
+        }
 
-        let SourceGraphBuilder { graph, .. } = builder;
+        for x in public {
+            graph[x].public = true;
+        }
 
-        let mut dependencies = match toposort(&graph, None) {
+        let mut dependencies = match toposort(&ordering(&graph), None) {
             Ok(x) => x,
-            Err(_) => bail!("found circular dependency in source files"),
+            Err(_) => return Err(cycle_error(&graph).into()),
         };
 
         dependencies.sort_by(|l, r| {
@@ -178,19 +495,42 @@ impl Sources {
             match (a.kind, b.kind) {
                 (SourceKind::Declaration, SourceKind::Implementation) => Ordering::Greater,
                 (SourceKind::Implementation, SourceKind::Declaration) => Ordering::Less,
-                (_, _) if *l == entry => Ordering::Greater,
-                (_, _) if *r == entry => Ordering::Less,
+                // Declarations are already in the right order, including the
+                // entry if it is one.
+                (SourceKind::Implementation, _) if *l == entry => Ordering::Greater,
+                (SourceKind::Implementation, _) if *r == entry => Ordering::Less,
                 _ => Ordering::Equal,
             }
         });
         dependencies.reverse();
 
+        let dependencies = symbol_order(&graph, &tokens, &dependencies);
+
         Ok(Self {
             graph,
+            entry,
             dependencies,
         })
     }
 
+    /// The source file everything else was found from.
+    pub fn entry(&self) -> &Source {
+        &self.graph[self.entry]
+    }
+
+    /// All source files, in the order they were found.
+    pub fn files(&self) -> impl Iterator<Item = &Source> {
+        self.graph.node_weights()
+    }
+
+    /// Every reference between two source files. The files are given as
+    /// indices into [`Sources::files`].
+    pub fn references(&self) -> impl Iterator<Item = (usize, usize, Reference)> + '_ {
+        self.graph
+            .edge_references()
+            .map(|x| (x.source().index(), x.target().index(), x.weight().reference))
+    }
+
     pub fn dependency_order(&self) -> DependencyOrder<'_> {
         DependencyOrder {
             graph: &self.graph,
@@ -224,6 +564,327 @@ impl ExactSizeIterator for DependencyOrder<'_> {
 
 impl FusedIterator for DependencyOrder<'_> {}
 
+/// The part of the source graph that decides the order of the bundle, before
+/// symbols are taken into account.
+///
+/// If a declaration file includes another declaration file, then we track
+/// this relation to order them and to detect cyclic dependencies.
+///
+/// However, if an implementation file includes a declaration file, then we
+/// will not keep track of this relation in order not to mess up the dependency
+/// finding algorithm. Basically if we were to track this relation, then the
+/// following would not work:
+///
+/// ```c
+/// // a.h:
+/// // cbundl: bundle
+/// #include "b.h"
+///
+/// void a();
+///
+/// // cbundl: impl=a.c
+///
+/// // a.c:
+/// // cbundl: bundle
+/// #include "b.h"
+///
+/// void a() { b(); }
+///
+/// // b.h:
+/// // cbundl: bundle
+/// #include "a.h"
+///
+/// void b();
+///
+/// // cbundl: impl=b.c
+///
+/// // b.c:
+/// // cbundl: bundle
+/// #include "a.h"
+///
+/// void b() { a(); }
+///
+/// // main.c:
+/// // cbundl: bundle
+/// #include "a.h"
+/// // cbundl: bundle
+/// #include "b.h"
+///
+/// int main() { /* ... */ }
+/// ```
+///
+/// The above example compiles successfully because even though a circlular
+/// dependency exists, the header files contain only function declarations,
+/// and thus it is perfectly fine if the implementation files include them
+/// in this manner. Including a file says nothing about what is actually
+/// needed from it.
+///
+/// What is actually needed is decided by [`symbol_order`], which looks at the
+/// symbols each file defines and uses. In the example above, `a.c` uses `b`,
+/// but `b.h` declares it and all declaration files come first anyway, so
+/// nothing changes. If `a.c` instead used a struct that `b.c` defines, `b.c`
+/// would be moved before it, and if `b.c` also needed a macro of `a.c`, the
+/// cycle would be reported as a warning. Symbols are found without
+/// preprocessing the files, so this can still be fooled by clever macros. It
+/// is then the responsibility of the compiler to reject invalid code.
+fn ordering<'a>(
+    graph: &'a Graph,
+) -> EdgeFiltered<&'a Graph, impl Fn(EdgeReference<'a, Dependency>) -> bool + 'a> {
+    EdgeFiltered::from_fn(graph, |x| {
+        graph[x.source()].kind == SourceKind::Declaration
+            && graph[x.target()].kind == SourceKind::Declaration
+    })
+}
+
+/// Reorder `order` so that every source file comes after the ones that
+/// define the symbols it uses, as far as that is possible.
+///
+/// Macros, enumerators and types that are needed complete must be defined
+/// before they are used, so uses of them are required to be ordered. Symbols
+/// are found without preprocessing, so a cycle of them is only a warning, and
+/// `order` is then kept as it is.
+///
+/// Functions and objects only need a declaration, which can also come from a
+/// prototype, and their names are easily mistaken for local variables. Uses
+/// of them only order the files where that does not lead to a cycle. So do
+/// includes between declaration files: a header that needs a macro of a
+/// header which includes it is better off before it.
+///
+/// Apart from that, the order is kept, so that files which don't use each
+/// other stay where they were.
+fn symbol_order(graph: &Graph, tokens: &[Tokens<'_>], order: &[NodeIndex]) -> Vec<NodeIndex> {
+    let mut constraints = Constraints::with_capacity(graph.node_count(), graph.edge_count());
+    for _ in graph.node_indices() {
+        constraints.add_node(());
+    }
+
+    let sources: Vec<&Source> = graph.node_weights().collect();
+    let (required, preferred): (Vec<_>, Vec<_>) = symbols::symbol_uses(&sources, tokens)
+        .into_iter()
+        .partition(|x| x.required);
+
+    for x in required {
+        let (user, provider) = (NodeIndex::new(x.user), NodeIndex::new(x.provider));
+
+        if !constraints.contains_edge(user, provider) {
+            let constraint = Constraint::Symbol {
+                symbol: x.symbol,
+                span: x.span,
+            };
+            constraints.add_edge(user, provider, constraint);
+        }
+    }
+
+    if is_cyclic_directed(&constraints) {
+        warn!(
+            target: DIAGNOSTIC_LOG_TARGET,
+            "{}",
+            constraint_cycle_warning(graph, &constraints)
+        );
+        return order.to_vec();
+    }
+
+    let ordering = ordering(graph);
+    let includes = ordering.edge_references().map(|edge| {
+        let constraint = Constraint::Include(edge.weight().span.clone());
+        (edge.source(), edge.target(), constraint)
+    });
+
+    let preferred = preferred.into_iter().map(|x| {
+        let constraint = Constraint::Symbol {
+            symbol: x.symbol,
+            span: x.span,
+        };
+        (
+            NodeIndex::new(x.user),
+            NodeIndex::new(x.provider),
+            constraint,
+        )
+    });
+
+    for (user, provider, constraint) in includes.chain(preferred) {
+        if constraints.contains_edge(user, provider)
+            || has_path_connecting(&constraints, provider, user, None)
+        {
+            continue;
+        }
+
+        constraints.add_edge(user, provider, constraint);
+    }
+
+    // Take the first file of the old order that has everything it needs
+    // before it, until none are left.
+    let mut done = vec![false; graph.node_count()];
+    let mut out = Vec::with_capacity(order.len());
+
+    while out.len() < order.len() {
+        let next = *order
+            .iter()
+            .find(|x| !done[x.index()] && constraints.neighbors(**x).all(|y| done[y.index()]))
+            .expect("constraints should not have a cycle");
+
+        done[next.index()] = true;
+        out.push(next);
+    }
+
+    out
+}
+
+/// Describe a cycle in `constraints`, which must have at least one.
+fn constraint_cycle_warning(graph: &Graph, constraints: &Constraints) -> Diagnostic {
+    let cycle = find_cycle(constraints);
+
+    let name = |idx: NodeIndex| display_path(&graph[idx].relative_path).to_string();
+
+    let mut diagnostic =
+        Diagnostic::warning("found circular dependency between symbols of source files");
+
+    for edge in &cycle {
+        let (from, to) = (edge.source(), edge.target());
+
+        let (span, label) = match edge.weight() {
+            Constraint::Include(span) => {
+                (span, format!("`{}` includes `{}`", name(from), name(to)))
+            }
+            Constraint::Symbol { symbol, span } => (
+                span,
+                format!("`{}` uses `{symbol}` from `{}`", name(from), name(to)),
+            ),
+        };
+
+        diagnostic = diagnostic.with_label(&graph[from].original, span.clone(), label);
+    }
+
+    let chain = cycle
+        .iter()
+        .map(|x| x.source())
+        .chain(cycle.first().map(|x| x.source()))
+        .map(|x| format!("`{}`", name(x)))
+        .collect::<Vec<_>>()
+        .join(" -> ");
+
+    diagnostic
+        .with_note(format!("cycle: {chain}"))
+        .with_note("the files are bundled in the order they are included")
+        .with_help("move what the files need from each other into a header that they include")
+}
+
+/// Describe a cycle in `graph`, which must have at least one.
+fn cycle_error(graph: &Graph) -> Diagnostic {
+    let ordering = ordering(graph);
+    let cycle: Vec<_> = find_cycle(&ordering).iter().map(|x| x.id()).collect();
+
+    let name = |idx: NodeIndex| display_path(&graph[idx].relative_path).to_string();
+
+    let mut diagnostic = Diagnostic::error("found circular dependency in source files");
+
+    for edge in &cycle {
+        let (from, to) = graph
+            .edge_endpoints(*edge)
+            .expect("edges of the cycle should exist");
+
+        diagnostic = diagnostic.with_label(
+            &graph[from].original,
+            graph[*edge].span.clone(),
+            format!("`{}` includes `{}`", name(from), name(to)),
+        );
+    }
+
+    let chain = cycle
+        .iter()
+        .map(|x| graph.edge_endpoints(*x).unwrap().0)
+        .chain(cycle.first().map(|x| graph.edge_endpoints(*x).unwrap().0))
+        .map(|x| format!("`{}`", name(x)))
+        .collect::<Vec<_>>()
+        .join(" -> ");
+
+    // The cycle starts at the file that was found first, so the last edge is
+    // the one that leads back to a file that was already being bundled.
+    // Removing it keeps the order in which the files are bundled.
+    let (back_from, back_to) = cycle
+        .last()
+        .and_then(|x| graph.edge_endpoints(*x))
+        .expect("cycle should have at least one edge");
+
+    diagnostic
+        .with_note(format!("cycle: {chain}"))
+        .with_help(format!(
+            "remove the bundle directive above the include of `{}` in `{}`",
+            name(back_to),
+            name(back_from)
+        ))
+}
+
+/// Find the edges of one cycle in `graph`. The cycle starts at the node that
+/// was added to the graph first.
+fn find_cycle<G>(graph: G) -> Vec<G::EdgeRef>
+where
+    G: IntoNodeIdentifiers + IntoNeighbors + IntoEdges + NodeIndexable,
+    G::NodeId: Ord + Hash,
+{
+    let component = tarjan_scc(graph)
+        .into_iter()
+        .find(|x| x.len() > 1 || graph.neighbors(x[0]).any(|y| y == x[0]))
+        .expect("graph should have a cycle");
+
+    let start = *component.iter().min().unwrap();
+
+    // Breadth-first search inside the component for the shortest way back to
+    // `start`.
+    let mut parent: HashMap<G::NodeId, G::EdgeRef> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(node) = queue.pop_front() {
+        for edge in graph.edges(node) {
+            let next = edge.target();
+            if !component.contains(&next) {
+                continue;
+            }
+
+            if next == start {
+                let mut cycle = vec![edge];
+                let mut at = node;
+                while at != start {
+                    let edge = parent[&at];
+                    cycle.push(edge);
+                    at = edge.source();
+                }
+
+                cycle.reverse();
+                return cycle;
+            }
+
+            if let Entry::Vacant(x) = parent.entry(next) {
+                x.insert(edge);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    unreachable!("every node of a strongly connected component is part of a cycle")
+}
+
+/// Find the deepest directory that contains all of `dirs`.
+fn common_root<'a, I>(mut dirs: I) -> PathBuf
+where
+    I: Iterator<Item = &'a Path>,
+{
+    let Some(first) = dirs.next() else {
+        return PathBuf::new();
+    };
+
+    let mut root = first.to_path_buf();
+    for dir in dirs {
+        while !dir.starts_with(&root) {
+            if !root.pop() {
+                return root;
+            }
+        }
+    }
+
+    root
+}
+
 fn chain<T, A, B>(a: A, b: B) -> Chain<A, B>
 where
     A: Iterator<Item = T>,
@@ -231,3 +892,344 @@ where
 {
     a.chain(b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::tests::strip_colors;
+    use crate::vfs::MemoryFs;
+
+    fn files(files: &[(&str, &str)]) -> MemoryFs {
+        let mut fs = MemoryFs::new();
+        for (path, content) in files {
+            fs.insert(path, *content);
+        }
+        fs
+    }
+
+    fn sources(fs: &MemoryFs, options: &SourcesOptions) -> Result<Sources, String> {
+        Sources::new(fs, PathBuf::from("/src/main.c"), options)
+            .map_err(|e| strip_colors(&e.to_string()))
+    }
+
+    fn order_with(fs: &MemoryFs, options: &SourcesOptions) -> Vec<String> {
+        sources(fs, options)
+            .unwrap()
+            .dependency_order()
+            .map(|x| x.relative_path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn order(fs: &MemoryFs) -> Vec<String> {
+        order_with(fs, &SourcesOptions::default())
+    }
+
+    fn error(fs: &MemoryFs, options: &SourcesOptions) -> String {
+        match sources(fs, options) {
+            Ok(_) => panic!("the sources should have an error"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn directives_outside_of_live_code_are_ignored() {
+        let fs = files(&[(
+            "/src/main.c",
+            "/*\n// cbundl: bundle\n#include \"a.h\"\n*/\n\
+             #if 0\n// cbundl: bundle\n#include \"b.h\"\n#endif\n\
+             const char *s = \"\\\n// cbundl: bundle\n\";\n",
+        )]);
+
+        assert_eq!(order(&fs), ["main.c"]);
+    }
+
+    #[test]
+    fn includes_are_found_next_to_the_file_first() {
+        let fs = files(&[
+            (
+                "/src/main.c",
+                "// cbundl: bundle\n#include \"a.h\"\n// cbundl: bundle\n#include \"b.h\"\n",
+            ),
+            ("/src/a.h", "int a(void);\n"),
+            ("/inc/a.h", "#error wrong a.h\n"),
+            ("/inc/b.h", "int b(void);\n"),
+        ]);
+
+        let options = SourcesOptions {
+            include_paths: vec![PathBuf::from("/inc")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            order_with(&fs, &options),
+            ["src/a.h", "inc/b.h", "src/main.c"]
+        );
+    }
+
+    #[test]
+    fn system_includes_need_system_include_paths() {
+        let fs = files(&[
+            ("/src/main.c", "// cbundl: bundle\n#include <a.h>\n"),
+            ("/src/a.h", "int a(void);\n"),
+            ("/sys/a.h", "int a(void);\n"),
+        ]);
+
+        let e = error(&fs, &SourcesOptions::default());
+        assert!(e.contains("cannot bundle a system include"), "{e}");
+
+        let options = SourcesOptions {
+            system_include_paths: vec![PathBuf::from("/sys")],
+            ..Default::default()
+        };
+
+        assert_eq!(order_with(&fs, &options), ["sys/a.h", "src/main.c"]);
+    }
+
+    #[test]
+    fn missing_includes_are_errors() {
+        let fs = files(&[(
+            "/src/main.c",
+            "// cbundl: bundle\n#include \"a.h\"\n// cbundl: bundle\n#include \"b.h\"\n",
+        )]);
+
+        let e = error(&fs, &SourcesOptions::default());
+        assert!(e.contains("failed to resolve path `/src/a.h`"), "{e}");
+        assert!(!e.contains("b.h"), "{e}");
+
+        let options = SourcesOptions {
+            keep_going: true,
+            include_paths: vec![PathBuf::from("/inc")],
+            ..Default::default()
+        };
+
+        let e = error(&fs, &options);
+        assert!(e.contains("failed to find `a.h`"), "{e}");
+        assert!(e.contains("failed to find `b.h`"), "{e}");
+        assert!(e.contains("searched in: `/src`, `/inc`"), "{e}");
+    }
+
+    #[test]
+    fn typedef_of_incomplete_struct_is_not_a_cycle() {
+        let fs = files(&[
+            (
+                "/src/main.c",
+                "// cbundl: bundle\n#include \"node.h\"\n\nint main(void) { return 0; }\n",
+            ),
+            (
+                "/src/node.h",
+                "typedef struct node node_t;\n\n// cbundl: bundle\n#include \"types.h\"\n\nstruct node {\n\tlist_t children;\n};\n",
+            ),
+            (
+                "/src/types.h",
+                "typedef struct list {\n\tnode_t *head;\n} list_t;\n\nvoid visit(struct node n);\nextern struct node root;\n",
+            ),
+        ]);
+
+        assert_eq!(order(&fs), ["types.h", "node.h", "main.c"]);
+        assert_uses_are_ordered(&fs);
+    }
+
+    #[test]
+    fn struct_used_by_value_is_ordered() {
+        let fs = files(&[
+            (
+                "/src/main.c",
+                "// cbundl: bundle\n#include \"b.h\"\n// cbundl: bundle\n#include \"a.h\"\n",
+            ),
+            ("/src/a.h", "struct point {\n\tint x, y;\n};\n"),
+            ("/src/b.h", "struct line {\n\tstruct point from, to;\n};\n"),
+        ]);
+
+        assert_eq!(order(&fs), ["a.h", "b.h", "main.c"]);
+        assert_uses_are_ordered(&fs);
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let fs = files(&[
+            ("/src/main.c", "// cbundl: bundle\n#include \"a.h\"\n"),
+            ("/src/a.h", "// cbundl: bundle\n#include \"b.h\"\n"),
+            ("/src/b.h", "// cbundl: bundle\n#include \"a.h\"\n"),
+        ]);
+
+        let e = error(&fs, &SourcesOptions::default());
+        assert!(
+            e.contains("found circular dependency in source files"),
+            "{e}"
+        );
+        assert!(e.contains("cycle: `a.h` -> `b.h` -> `a.h`"), "{e}");
+    }
+
+    /// A program with the headers `a.h` and `b.h`, implemented by `a.c` and
+    /// `b.c`.
+    fn program(a: &str, b: &str) -> MemoryFs {
+        files(&[
+            (
+                "/src/main.c",
+                "// cbundl: bundle\n#include \"a.h\"\n// cbundl: bundle\n#include \"b.h\"\n",
+            ),
+            ("/src/a.h", "int a(void);\n// cbundl: impl=a.c\n"),
+            ("/src/b.h", "int b(void);\n// cbundl: impl=b.c\n"),
+            ("/src/a.c", a),
+            ("/src/b.c", b),
+        ])
+    }
+
+    #[test]
+    fn static_collisions_are_errors() {
+        let fs = program(
+            "static int count;\nstatic int a;\n",
+            "static int count(void) { return 0; }\n#ifdef B\nstatic int a;\n#endif\n",
+        );
+
+        let e = error(&fs, &SourcesOptions::default());
+        assert!(
+            e.contains("static symbol `count` is defined in more than one file"),
+            "{e}"
+        );
+        assert!(
+            e.contains("static symbol `a` is defined in more than one file"),
+            "{e}"
+        );
+
+        let options = SourcesOptions {
+            rename_statics: true,
+            ..Default::default()
+        };
+
+        let sources = sources(&fs, &options).unwrap();
+        let content = |name: &str| {
+            sources
+                .files()
+                .find(|x| x.relative_path == Path::new(name))
+                .unwrap()
+                .content
+                .clone()
+        };
+
+        assert_eq!(content("a.c"), "static int a_c_count;\nstatic int a_c_a;\n");
+        assert_eq!(
+            content("b.c"),
+            "static int b_c_count(void) { return 0; }\n#ifdef B\nstatic int b_c_a;\n#endif\n"
+        );
+    }
+
+    #[test]
+    fn statics_are_not_renamed_to_names_in_use() {
+        let options = SourcesOptions {
+            rename_statics: true,
+            ..Default::default()
+        };
+
+        // Renaming `count` in `b.c` is enough.
+        let fs = program("static int count;\nint a_c_count;\n", "static int count;\n");
+        let sources = sources(&fs, &options).unwrap();
+        let renamed: Vec<_> = sources
+            .files()
+            .filter(|x| !x.renamed.is_empty())
+            .map(|x| (x.relative_path.clone(), x.renamed.clone()))
+            .collect();
+        assert_eq!(
+            renamed,
+            [(
+                PathBuf::from("b.c"),
+                vec![("count".to_owned(), "b_c_count".to_owned())]
+            )]
+        );
+
+        let fs = program(
+            "static int count;\nint a_c_count;\n",
+            "static int count;\nint b_c_count;\n",
+        );
+        let e = error(&fs, &options);
+        assert!(
+            e.contains("static symbol `count` is defined in more than one file"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn static_definitions_under_exclusive_conditions_are_fine() {
+        let fs = program(
+            "#ifdef FAST\nstatic int mode;\n#endif\n",
+            "#ifndef FAST\nstatic int mode;\n#endif\n",
+        );
+
+        assert!(sources(&fs, &SourcesOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn external_definitions_in_more_than_one_file_are_errors() {
+        let fs = program(
+            "int shared(void) { return 1; }\nint x;\ninline int f(void) { return 0; }\n",
+            "int shared(void) { return 2; }\nint x;\ninline int f(void) { return 0; }\n",
+        );
+
+        let e = error(&fs, &SourcesOptions::default());
+        assert!(
+            e.contains("`shared` is defined in more than one file"),
+            "{e}"
+        );
+        assert!(!e.contains("`x`"), "{e}");
+        assert!(!e.contains("`f`"), "{e}");
+    }
+
+    /// Check that every file in `fs` comes after the files that define the
+    /// macros and types it needs.
+    fn assert_uses_are_ordered(fs: &MemoryFs) {
+        let sources = sources(fs, &SourcesOptions::default()).unwrap();
+        let files: Vec<&Source> = sources.dependency_order().collect();
+        let tokens: Vec<Tokens<'_>> = files
+            .iter()
+            .map(|x| Tokens::new(&x.original.text))
+            .collect();
+
+        for x in symbols::symbol_uses(&files, &tokens) {
+            assert!(
+                !x.required || x.provider < x.user,
+                "`{}` is used by `{}` before `{}` defines it",
+                x.symbol,
+                files[x.user].relative_path.display(),
+                files[x.provider].relative_path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn implementation_files_are_ordered_by_uses() {
+        let fs = program(
+            "static struct point origin = { 1, 2 };\n\nint a(void) {\n\treturn origin.x * FACTOR;\n}\n",
+            "#define FACTOR 2\n\nstruct point {\n\tint x, y;\n};\n\nint b(void) {\n\treturn 0;\n}\n",
+        );
+
+        assert_eq!(order(&fs), ["a.h", "b.h", "main.c", "b.c", "a.c"]);
+        assert_uses_are_ordered(&fs);
+    }
+
+    #[test]
+    fn symbol_uses_come_before_includes() {
+        // `a.h` includes `b.h`, but `b.h` needs a macro of `a.h`. Only the
+        // order with `a.h` first compiles.
+        let fs = files(&[
+            ("/src/main.c", "// cbundl: bundle\n#include \"a.h\"\n"),
+            (
+                "/src/a.h",
+                "#define A_SIZE 4\n\n// cbundl: bundle\n#include \"b.h\"\n",
+            ),
+            ("/src/b.h", "extern int b[A_SIZE];\n"),
+        ]);
+
+        assert_eq!(order(&fs), ["a.h", "b.h", "main.c"]);
+        assert_uses_are_ordered(&fs);
+    }
+
+    #[test]
+    fn symbol_cycle_keeps_include_order() {
+        let fs = program(
+            "#define FACTOR 2\n\nstatic struct point origin = { 1, 2 };\n",
+            "struct point {\n\tint x, y;\n};\n\nint b(void) {\n\treturn FACTOR;\n}\n",
+        );
+
+        assert_eq!(order(&fs), ["a.h", "b.h", "main.c", "a.c", "b.c"]);
+    }
+}
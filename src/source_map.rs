@@ -0,0 +1,136 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::line_markers::Location;
+
+const VERSION: u32 = 1;
+
+/// Maps line ranges of a bundle back to the original source files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub version: u32,
+    pub sources: Vec<String>,
+    pub mappings: Vec<Mapping>,
+}
+
+/// A range of consecutive bundle lines that came from consecutive lines of
+/// the same source file. All line numbers are 1-based and inclusive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub bundle_start: usize,
+    pub bundle_end: usize,
+
+    /// Index into [`SourceMap::sources`].
+    pub source: usize,
+
+    /// The line in the source file that corresponds to `bundle_start`.
+    pub line: usize,
+}
+
+impl SourceMap {
+    /// Build a source map from the location of each line of a bundle.
+    pub fn new(locations: &[Option<Location>]) -> Self {
+        let mut sources: Vec<String> = Vec::new();
+        let mut mappings: Vec<Mapping> = Vec::new();
+
+        for (i, location) in locations.iter().enumerate() {
+            let Some(location) = location else {
+                continue;
+            };
+
+            let bundle_line = i + 1;
+
+            let source = match sources.iter().position(|x| *x == location.path) {
+                Some(x) => x,
+                None => {
+                    sources.push(location.path.clone());
+                    sources.len() - 1
+                }
+            };
+
+            if let Some(last) = mappings.last_mut() {
+                let is_continuation = last.source == source
+                    && last.bundle_end + 1 == bundle_line
+                    && last.line + (last.bundle_end - last.bundle_start) + 1 == location.line;
+
+                if is_continuation {
+                    last.bundle_end = bundle_line;
+                    continue;
+                }
+            }
+
+            mappings.push(Mapping {
+                bundle_start: bundle_line,
+                bundle_end: bundle_line,
+                source,
+                line: location.line,
+            });
+        }
+
+        Self {
+            version: VERSION,
+            sources,
+            mappings,
+        }
+    }
+
+    /// Find where line `line` of the bundle came from.
+    pub fn lookup(&self, line: usize) -> Option<Location> {
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|x| (x.bundle_start..=x.bundle_end).contains(&line))?;
+
+        Some(Location {
+            path: self.sources.get(mapping.source)?.clone(),
+            line: mapping.line + (line - mapping.bundle_start),
+        })
+    }
+
+    /// The path of the source map that belongs to the bundle at `bundle`.
+    pub fn sidecar_path(bundle: &Path) -> PathBuf {
+        let mut x = OsString::from(bundle.as_os_str());
+        x.push(".map.json");
+        PathBuf::from(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vfs::MemoryFs;
+    use crate::BundleOptions;
+
+    #[test]
+    fn generated_lines_map_to_nothing() {
+        let mut fs = MemoryFs::new();
+        fs.insert("/src/a.h", "int a(void);");
+        fs.insert(
+            "/src/main.c",
+            "// cbundl: bundle\n#include \"a.h\"\n\nint main(void) { return a(); }\n",
+        );
+
+        let bundle = BundleOptions::new("/src/main.c")
+            .format(false)
+            .quotes(false)
+            .source_map(true)
+            .bundle_in(&fs)
+            .unwrap();
+        let source_map = bundle.source_map.unwrap();
+        assert_eq!(source_map.sources, ["a.h", "main.c"]);
+
+        let lines: Vec<&str> = bundle.code.lines().collect();
+        for (i, line) in lines.iter().enumerate() {
+            let location = source_map.lookup(i + 1);
+
+            match location {
+                Some(x) if x.path == "a.h" => {
+                    assert_eq!((x.line, *line), (1, "int a(void);"));
+                }
+                Some(x) => assert!(x.path == "main.c" && x.line <= 4, "{x:?}"),
+                None => assert!(!line.starts_with("int"), "{line}"),
+            }
+        }
+    }
+}
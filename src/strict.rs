@@ -0,0 +1,288 @@
+//! Strict deserialization of TOML files, where keys that don't mean anything
+//! are errors instead of being silently ignored.
+
+use std::fmt;
+use std::ops::Range;
+
+use eyre::Context;
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde::Deserialize;
+use toml_edit::{ImDocument, Item, TableLike, Value};
+
+use cbundl::{Diagnostic, Diagnostics, SourceText};
+
+/// One step of the path to a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Deserialize `source` and report every key that `T` does not know about.
+///
+/// `known_keys` gets the path of the table that contains an unknown key and
+/// returns the keys that are valid in it, which are used to suggest a
+/// replacement.
+pub fn from_str<T, F>(source: &SourceText, known_keys: F) -> eyre::Result<T>
+where
+    T: DeserializeOwned,
+    F: Fn(&[Segment]) -> &'static [&'static str],
+{
+    let mut unknown: Vec<Vec<Segment>> = Vec::new();
+
+    let x = serde_ignored::deserialize(toml::Deserializer::new(&source.text), |path| {
+        unknown.push(segments(&path));
+    })
+    .context("failed to parse file")?;
+
+    if unknown.is_empty() {
+        return Ok(x);
+    }
+
+    // The file has already been parsed successfully, so this can't fail.
+    let document = ImDocument::parse(&*source.text).ok();
+
+    let diagnostics = unknown
+        .into_iter()
+        .map(|path| {
+            let (key, table) = path.split_last().expect("unknown keys are never the root");
+
+            let name = match key {
+                Segment::Key(x) => x.as_str(),
+                Segment::Index(_) => unreachable!("array elements are never unknown"),
+            };
+
+            let mut diagnostic = Diagnostic::error(format!("unknown key `{}`", display(&path)));
+
+            if let Some(span) = document.as_ref().and_then(|x| key_span(x, &path)) {
+                diagnostic = diagnostic.with_span(source, span);
+            }
+
+            match suggest(name, known_keys(table)) {
+                Some(x) => diagnostic.with_help(format!("did you mean `{x}`?")),
+                None => diagnostic,
+            }
+        })
+        .collect();
+
+    Err(Diagnostics::new(diagnostics).into())
+}
+
+/// Get the names of the fields of `T`, as they are written in a file.
+pub fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+    #[derive(Debug)]
+    struct Done;
+
+    impl fmt::Display for Done {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("done")
+        }
+    }
+
+    impl std::error::Error for Done {}
+
+    impl de::Error for Done {
+        fn custom<E: fmt::Display>(_: E) -> Self {
+            Self
+        }
+    }
+
+    impl<'de> de::Deserializer<'de> for FieldsDeserializer<'_> {
+        type Error = Done;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Done> {
+            Err(Done)
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Done> {
+            *self.0 = fields;
+            Err(Done)
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields
+}
+
+fn segments(path: &serde_ignored::Path<'_>) -> Vec<Segment> {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => Vec::new(),
+        Path::Seq { parent, index } => {
+            let mut x = segments(parent);
+            x.push(Segment::Index(*index));
+            x
+        }
+        Path::Map { parent, key } => {
+            let mut x = segments(parent);
+            x.push(Segment::Key(key.clone()));
+            x
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => segments(parent),
+    }
+}
+
+fn display(path: &[Segment]) -> String {
+    let mut out = String::new();
+
+    for segment in path {
+        match segment {
+            Segment::Key(x) if out.is_empty() => out.push_str(x),
+            Segment::Key(x) => {
+                out.push('.');
+                out.push_str(x);
+            }
+            Segment::Index(i) => out.push_str(&format!("[{i}]")),
+        }
+    }
+
+    out
+}
+
+/// Find where the last key of `path` is written in `document`.
+fn key_span(document: &ImDocument<&str>, path: &[Segment]) -> Option<Range<usize>> {
+    #[derive(Clone, Copy)]
+    enum Node<'a> {
+        Table(&'a dyn TableLike),
+        Item(&'a Item),
+        Value(&'a Value),
+    }
+
+    let mut node = Node::Table(document.as_table());
+    let mut span = None;
+
+    for segment in path {
+        let table = match node {
+            Node::Table(x) => Some(x),
+            Node::Item(x) => x.as_table_like(),
+            Node::Value(x) => x.as_inline_table().map(|x| x as &dyn TableLike),
+        };
+
+        node = match segment {
+            Segment::Key(key) => {
+                let (key, item) = table?.get_key_value(key)?;
+                span = key.span();
+                Node::Item(item)
+            }
+            Segment::Index(i) => match node {
+                Node::Item(Item::ArrayOfTables(x)) => Node::Table(x.get(*i)?),
+                Node::Item(Item::Value(Value::Array(x))) => Node::Value(x.get(*i)?),
+                _ => return None,
+            },
+        };
+    }
+
+    span
+}
+
+/// Find the key in `candidates` that `key` was most likely meant to be.
+fn suggest(key: &str, candidates: &[&'static str]) -> Option<&'static str> {
+    const THRESHOLD: f64 = 0.7;
+
+    candidates
+        .iter()
+        .map(|x| (strsim::jaro(key, x), *x))
+        .filter(|(score, _)| *score > THRESHOLD)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, x)| x)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct File {
+        bundle: Option<Bundle>,
+        #[serde(default)]
+        target: Vec<Bundle>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Bundle {
+        line_markers: Option<bool>,
+        source_map: Option<bool>,
+    }
+
+    fn parse(text: &str) -> eyre::Result<File> {
+        let source = SourceText::new(PathBuf::from("cbundl.toml"), text);
+
+        from_str(&source, |path| match path {
+            [] => fields::<File>(),
+            [Segment::Key(x)] if x == "bundle" => fields::<Bundle>(),
+            [Segment::Key(x), Segment::Index(_)] if x == "target" => fields::<Bundle>(),
+            _ => &[],
+        })
+    }
+
+    #[test]
+    fn fields_of_a_struct() {
+        assert_eq!(fields::<File>(), ["bundle", "target"]);
+        assert_eq!(fields::<Bundle>(), ["line_markers", "source_map"]);
+        assert_eq!(fields::<bool>(), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn known_keys_are_fine() {
+        let file =
+            parse("[bundle]\nline_markers = true\n\n[[target]]\nsource_map = true\n").unwrap();
+
+        assert_eq!(file.bundle.unwrap().line_markers, Some(true));
+        assert_eq!(file.target[0].source_map, Some(true));
+    }
+
+    #[test]
+    fn typo_in_nested_key() {
+        let e = parse("[bundle]\nline_marker = true\n\n[[target]]\nsorce_map = true\n")
+            .unwrap_err()
+            .to_string();
+
+        assert!(e.contains("unknown key `bundle.line_marker`"), "{e}");
+        assert!(e.contains(":2:1"), "{e}");
+        assert!(e.contains("did you mean `line_markers`?"), "{e}");
+
+        assert!(e.contains("unknown key `target[0].sorce_map`"), "{e}");
+        assert!(e.contains(":5:1"), "{e}");
+        assert!(e.contains("did you mean `source_map`?"), "{e}");
+    }
+
+    #[test]
+    fn key_with_no_close_match() {
+        let e = parse("frobnicate = 1\n\n[bundle]\ncolor = true\n")
+            .unwrap_err()
+            .to_string();
+
+        assert!(e.contains("unknown key `frobnicate`"), "{e}");
+        assert!(e.contains("unknown key `bundle.color`"), "{e}");
+        assert!(!e.contains("did you mean"), "{e}");
+    }
+
+    #[test]
+    fn suggestions() {
+        let candidates = ["line_markers", "source_map", "separators"];
+
+        assert_eq!(suggest("line_marker", &candidates), Some("line_markers"));
+        assert_eq!(suggest("sepraators", &candidates), Some("separators"));
+        assert_eq!(suggest("color", &candidates), None);
+        assert_eq!(suggest("x", &candidates), None);
+    }
+}
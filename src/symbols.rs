@@ -0,0 +1,650 @@
+//! Checks on the symbols that the source files define, which only matter once
+//! the files are put into the same translation unit.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::diagnostic::{Diagnostic, Span};
+use crate::display::display_path;
+use crate::parse::declaration::{declarations, Declaration, Declarator, DeclaratorKind, Storage};
+use crate::parse::tokens::{TokenKind, Tokens};
+use crate::source::{Source, SourceKind};
+
+/// A definition of a symbol at file scope.
+#[derive(Debug)]
+struct Definition {
+    /// Index of the source file the definition is in.
+    source: usize,
+    span: Span,
+
+    /// The preprocessor conditions the definition is compiled under.
+    condition: Vec<String>,
+}
+
+impl Definition {
+    /// Whether both definitions are in different files and can be compiled at
+    /// the same time. Groups with different conditions are assumed to
+    /// exclude each other.
+    fn overlaps(&self, other: &Self) -> bool {
+        self.source != other.source
+            && (self.condition.is_empty()
+                || other.condition.is_empty()
+                || self.condition == other.condition)
+    }
+}
+
+/// Find the file scope `static` symbols that more than one source file
+/// defines. In separate translation units they were distinct, in the bundle
+/// they would clash.
+///
+/// If `rename` is set, the symbols are renamed in implementation files
+/// instead, by prefixing them with the path of the file. Collisions between
+/// declaration files are always reported, as their symbols are meant to be
+/// used by other files. So are symbols whose new name the file already uses.
+///
+/// `tokens` are the tokens of the original text of each source file.
+pub fn check_statics(
+    sources: &mut [&mut Source],
+    tokens: &[Tokens<'_>],
+    rename: bool,
+) -> Vec<Diagnostic> {
+    let definitions = definitions(tokens, |declaration, declarator| {
+        declaration.storage == Storage::Static && declaration.defines(declarator)
+    });
+
+    let mut renames: Vec<Vec<&str>> = vec![Vec::new(); sources.len()];
+    let mut errors = Vec::new();
+
+    for (name, definitions) in &definitions {
+        let mut colliding = collisions(definitions.iter());
+
+        if rename {
+            colliding.retain(|x| {
+                if sources[x.source].kind != SourceKind::Implementation {
+                    return true;
+                }
+
+                let new_name = symbol_prefix(&sources[x.source].relative_path) + name;
+                if uses_name(&tokens[x.source], &new_name) {
+                    return true;
+                }
+
+                if !renames[x.source].contains(&name.as_str()) {
+                    renames[x.source].push(name);
+                }
+                false
+            });
+
+            colliding = collisions(colliding.into_iter());
+        }
+
+        if colliding.is_empty() {
+            continue;
+        }
+
+        let mut error = Diagnostic::error(format!(
+            "static symbol `{name}` is defined in more than one file"
+        ));
+
+        for x in colliding {
+            error = error.with_label(&sources[x.source].original, x.span.clone(), "defined here");
+        }
+
+        let help = if rename {
+            "give each of them a different name"
+        } else {
+            "give each of them a different name or enable `rename_statics` to have them renamed"
+        };
+
+        errors.push(error.with_help(help));
+    }
+
+    if errors.is_empty() {
+        for (source, names) in sources.iter_mut().zip(renames) {
+            if !names.is_empty() {
+                rename_symbols(source, &names);
+            }
+        }
+    }
+
+    errors
+}
+
+/// Find the functions and objects with external linkage that more than one
+/// source file defines. This happens when two `impl` directives name files
+/// that define the same things.
+///
+/// Tentative definitions, like `int x;`, are left out, as any number of them
+/// can be merged into one definition in the same translation unit. So are
+/// `inline` functions without `extern`, which don't define an external symbol.
+pub fn check_externals(sources: &[&Source], tokens: &[Tokens<'_>]) -> Vec<Diagnostic> {
+    let definitions = definitions(tokens, |declaration, declarator| {
+        let external = match declaration.storage {
+            Storage::Default => !declaration.inline,
+            Storage::Extern => true,
+            Storage::Static | Storage::Typedef => false,
+        };
+
+        let tentative = declarator.kind == DeclaratorKind::Object && !declarator.initialized;
+
+        external && !tentative && declaration.defines(declarator)
+    });
+
+    let mut errors = Vec::new();
+
+    for (name, definitions) in &definitions {
+        let colliding = collisions(definitions.iter());
+        if colliding.is_empty() {
+            continue;
+        }
+
+        let mut error = Diagnostic::error(format!("`{name}` is defined in more than one file"));
+
+        for x in colliding {
+            error = error.with_label(&sources[x.source].original, x.span.clone(), "defined here");
+        }
+
+        errors.push(
+            error
+                .with_note("every source file ends up in the same translation unit")
+                .with_help(
+                    "check that no two `impl` directives name files that define the same symbols",
+                ),
+        );
+    }
+
+    errors
+}
+
+/// Keep only the definitions that overlap with another one.
+fn collisions<'a>(
+    definitions: impl Iterator<Item = &'a Definition> + Clone,
+) -> Vec<&'a Definition> {
+    definitions
+        .clone()
+        .filter(|x| definitions.clone().any(|y| x.overlaps(y)))
+        .collect()
+}
+
+/// Find the file scope definitions in the tokens of every source file that
+/// `filter` accepts.
+fn definitions<F>(tokens: &[Tokens<'_>], filter: F) -> BTreeMap<String, Vec<Definition>>
+where
+    F: Fn(&Declaration, &Declarator) -> bool,
+{
+    let mut out: BTreeMap<String, Vec<Definition>> = BTreeMap::new();
+
+    for (i, tokens) in tokens.iter().enumerate() {
+        for declaration in declarations(tokens) {
+            for declarator in &declaration.declarators {
+                if !filter(&declaration, declarator) {
+                    continue;
+                }
+
+                let token = &tokens.tokens[declarator.name];
+                out.entry(tokens.text(token).to_owned())
+                    .or_default()
+                    .push(Definition {
+                        source: i,
+                        span: token.span.clone(),
+                        condition: tokens.conditions[token.condition].clone(),
+                    });
+            }
+        }
+    }
+
+    out
+}
+
+/// A symbol that one source file uses and another one defines.
+#[derive(Debug, Clone)]
+pub struct SymbolUse {
+    /// Index of the source file that uses the symbol.
+    pub user: usize,
+    /// Index of the source file that defines it.
+    pub provider: usize,
+
+    /// The symbol as it is written, like `MAX` or `struct point`.
+    pub symbol: String,
+    /// Span of the first use of the symbol inside the user.
+    pub span: Span,
+
+    /// The user can only be compiled after the definition, like for macros
+    /// and types. Otherwise a declaration, like a prototype, would do just as
+    /// well, and the user only prefers to come after the provider.
+    pub required: bool,
+}
+
+/// The two kinds of names in C that matter for ordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Namespace {
+    /// The tags of structs, unions and enums.
+    Tag,
+    /// Everything else: macros, typedefs, enumerators, functions and objects.
+    Ordinary,
+}
+
+/// What a source file offers to the other ones under a name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Provides {
+    /// Nothing, like a `static` function. The name is still known inside the
+    /// file itself.
+    Nothing,
+    /// A declaration, like a prototype or an `extern` object.
+    Declaration,
+    /// A type, which must come before the uses that need it to be complete.
+    Type,
+    /// A macro or an enumerator, which must come before it is used.
+    Definition,
+}
+
+#[derive(Debug, Clone)]
+struct Use {
+    /// The name as it is written, with the keyword for tags.
+    symbol: String,
+    span: Span,
+    /// The use needs the complete definition of a type. Pointers, `typedef`s,
+    /// prototypes and `extern` declarations don't.
+    complete: bool,
+}
+
+/// The names a source file defines and uses.
+#[derive(Debug, Default)]
+struct Scan<'a> {
+    defines: HashMap<(Namespace, &'a str), Provides>,
+    uses: HashMap<(Namespace, &'a str), Use>,
+}
+
+impl<'a> Scan<'a> {
+    fn define(&mut self, namespace: Namespace, name: &'a str, provides: Provides) {
+        let x = self.defines.entry((namespace, name)).or_insert(provides);
+        *x = (*x).max(provides);
+    }
+
+    fn add_use(&mut self, namespace: Namespace, name: &'a str, x: Use) {
+        let old = self
+            .uses
+            .entry((namespace, name))
+            .or_insert_with(|| x.clone());
+        if !old.complete && x.complete {
+            *old = x;
+        }
+    }
+}
+
+/// Find the symbols that each source file uses from the others.
+///
+/// Only files of the same kind are paired up. Declaration files are always
+/// bundled before implementation files, so when a declaration file provides a
+/// name, implementation files get it from there. Declaration files cannot
+/// rely on implementation files at all.
+pub fn symbol_uses(sources: &[&Source], tokens: &[Tokens<'_>]) -> Vec<SymbolUse> {
+    let scans: Vec<Scan<'_>> = tokens.iter().map(scan).collect();
+
+    let mut providers: HashMap<(Namespace, &str), Vec<(usize, Provides)>> = HashMap::new();
+    for (i, scan) in scans.iter().enumerate() {
+        for (name, provides) in &scan.defines {
+            if *provides != Provides::Nothing {
+                providers.entry(*name).or_default().push((i, *provides));
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+
+    for (user, scan) in scans.iter().enumerate() {
+        let kind = sources[user].kind;
+
+        let mut uses: Vec<_> = scan
+            .uses
+            .iter()
+            .filter(|(name, _)| !scan.defines.contains_key(name))
+            .collect();
+        uses.sort_by_key(|(_, x)| x.span.start);
+
+        for (name, x) in uses {
+            let Some(providers) = providers.get(name) else {
+                continue;
+            };
+
+            if kind == SourceKind::Implementation
+                && providers
+                    .iter()
+                    .any(|(i, _)| sources[*i].kind == SourceKind::Declaration)
+            {
+                continue;
+            }
+
+            for (provider, provides) in providers {
+                if sources[*provider].kind != kind {
+                    continue;
+                }
+
+                out.push(SymbolUse {
+                    user,
+                    provider: *provider,
+                    symbol: x.symbol.clone(),
+                    span: x.span.clone(),
+                    required: match provides {
+                        Provides::Definition => true,
+                        Provides::Type => x.complete,
+                        Provides::Nothing | Provides::Declaration => false,
+                    },
+                });
+            }
+        }
+    }
+
+    out.sort_by_key(|x| (x.user, x.span.start, x.provider));
+    out
+}
+
+/// Find the names the file defines and uses. Code in directives is not looked
+/// at, apart from the names of macros, because macros are only expanded where
+/// they are used.
+fn scan<'a>(tokens: &Tokens<'a>) -> Scan<'a> {
+    let mut scan = Scan::default();
+    let declarations = declarations(tokens);
+
+    for declaration in &declarations {
+        for declarator in &declaration.declarators {
+            let name = tokens.text(&tokens.tokens[declarator.name]);
+
+            let provides = match declaration.storage {
+                Storage::Typedef => Provides::Type,
+                Storage::Static => Provides::Nothing,
+                Storage::Default | Storage::Extern => Provides::Declaration,
+            };
+
+            scan.define(Namespace::Ordinary, name, provides);
+        }
+    }
+
+    for (i, token) in tokens.tokens.iter().enumerate() {
+        if token.directive
+            && tokens.is(i, "define")
+            && i >= 1
+            && tokens.is(i - 1, "#")
+            && tokens
+                .tokens
+                .get(i + 1)
+                .is_some_and(|x| x.directive && x.kind == TokenKind::Ident)
+        {
+            let name = tokens.text(&tokens.tokens[i + 1]);
+            scan.define(Namespace::Ordinary, name, Provides::Definition);
+        }
+    }
+
+    // The declaration each token belongs to, if any.
+    let mut owner: Vec<Option<&Declaration>> = vec![None; tokens.tokens.len()];
+    for declaration in &declarations {
+        owner[declaration.tokens.clone()].fill(Some(declaration));
+    }
+
+    let code: Vec<usize> = (0..tokens.tokens.len())
+        .filter(|x| !tokens.tokens[*x].directive)
+        .collect();
+    let text = |i: usize| code.get(i).map_or("", |x| tokens.text(&tokens.tokens[*x]));
+
+    // Whether the type that starts at `start` and whose name is at `name` is
+    // needed complete. That is the case where it is used by value in a
+    // definition, as a member or with `sizeof`, but not when only a pointer
+    // to it is used, or in a `typedef`, a prototype or an `extern`
+    // declaration.
+    let complete = |start: usize, name: usize, depth: usize| {
+        if text(name + 1) == "*" {
+            return false;
+        }
+
+        if start >= 2 && text(start - 1) == "(" && text(start - 2) == "sizeof" {
+            return true;
+        }
+
+        if depth > 0 {
+            return true;
+        }
+
+        match owner[code[name]] {
+            None => true,
+            Some(x) if matches!(x.storage, Storage::Typedef | Storage::Extern) => false,
+            Some(x) => {
+                x.body
+                    || x.declarators
+                        .iter()
+                        .any(|x| x.kind == DeclaratorKind::Object)
+            }
+        }
+    };
+
+    let mut depth = 0usize;
+    // The depth of the enum body the scan is in, if any, and whether the
+    // next identifier names an enumerator.
+    let mut enum_body = None;
+    let mut enumerator = false;
+    let mut tag = false;
+
+    for (i, token) in code.iter().map(|x| &tokens.tokens[*x]).enumerate() {
+        let t = text(i);
+        let after_tag = std::mem::take(&mut tag);
+
+        match t {
+            "{" => {
+                if text(i.wrapping_sub(1)) == "enum" || text(i.wrapping_sub(2)) == "enum" {
+                    enum_body = Some(depth);
+                    enumerator = true;
+                }
+                depth += 1;
+                continue;
+            }
+            "}" => {
+                depth = depth.saturating_sub(1);
+                if enum_body == Some(depth) {
+                    enum_body = None;
+                }
+                continue;
+            }
+            "," if enum_body.is_some_and(|x| x + 1 == depth) => {
+                enumerator = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if token.kind != TokenKind::Ident {
+            continue;
+        }
+
+        if matches!(t, "struct" | "union" | "enum") {
+            tag = true;
+            continue;
+        }
+
+        if after_tag {
+            let next = text(i + 1);
+
+            if next == "{" {
+                scan.define(Namespace::Tag, t, Provides::Type);
+            } else {
+                let x = Use {
+                    symbol: format!("{} {t}", text(i - 1)),
+                    span: token.span.clone(),
+                    complete: next != ";" && complete(i - 1, i, depth),
+                };
+                scan.add_use(Namespace::Tag, t, x);
+            }
+
+            continue;
+        }
+
+        if std::mem::take(&mut enumerator) {
+            scan.define(Namespace::Ordinary, t, Provides::Definition);
+            continue;
+        }
+
+        if matches!(text(i.wrapping_sub(1)), "." | "->") {
+            continue;
+        }
+
+        let x = Use {
+            symbol: t.to_owned(),
+            span: token.span.clone(),
+            complete: complete(i, i, depth),
+        };
+        scan.add_use(Namespace::Ordinary, t, x);
+    }
+
+    scan
+}
+
+/// Rename every use of `names` in `source` by prefixing them with the path of
+/// the file, and remember the new names in [`Source::renamed`].
+fn rename_symbols(source: &mut Source, names: &[&str]) {
+    let prefix = symbol_prefix(&source.relative_path);
+
+    for name in names {
+        debug!(
+            "renaming static symbol `{name}` of `{}` to `{prefix}{name}`",
+            display_path(&source.relative_path)
+        );
+
+        source
+            .renamed
+            .push(((*name).to_owned(), format!("{prefix}{name}")));
+    }
+
+    source.content = rename_identifiers(&source.content, &source.renamed);
+}
+
+/// Rename the identifiers in `code` according to `renames`, which maps old
+/// names to new ones. Members of structs and unions are left alone, even if
+/// they have the same name.
+pub(crate) fn rename_identifiers(code: &str, renames: &[(String, String)]) -> String {
+    let tokens = Tokens::new(code);
+
+    let mut out = String::with_capacity(code.len());
+    let mut last = 0;
+
+    // Whether each open brace is the body of a struct or union.
+    let mut braces: Vec<bool> = Vec::new();
+
+    for (i, token) in tokens.tokens.iter().enumerate() {
+        let text = tokens.text(token);
+
+        match text {
+            "{" => {
+                let aggregate = |x: usize| tokens.is(x, "struct") || tokens.is(x, "union");
+                braces.push(i >= 1 && aggregate(i - 1) || i >= 2 && aggregate(i - 2));
+            }
+            "}" => {
+                braces.pop();
+            }
+            _ => {}
+        }
+
+        let is_member = i >= 1 && (tokens.is(i - 1, ".") || tokens.is(i - 1, "->"))
+            || braces.iter().any(|x| *x);
+
+        if token.kind != TokenKind::Ident || is_member {
+            continue;
+        }
+
+        let Some((_, new)) = renames.iter().find(|(old, _)| old == text) else {
+            continue;
+        };
+
+        out.push_str(&code[last..token.span.start]);
+        out.push_str(new);
+        last = token.span.end;
+    }
+
+    out.push_str(&code[last..]);
+    out
+}
+
+/// Whether `name` is used as an identifier anywhere in `tokens`.
+fn uses_name(tokens: &Tokens<'_>, name: &str) -> bool {
+    tokens
+        .tokens
+        .iter()
+        .any(|x| x.kind == TokenKind::Ident && tokens.text(x) == name)
+}
+
+/// Turn `path` into a prefix for the symbols of the file, like `src_util_c_`
+/// for `src/util.c`.
+fn symbol_prefix(path: &Path) -> String {
+    let mut out: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        out.insert(0, '_');
+    }
+
+    out.push('_');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether each symbol that `code` uses is needed complete.
+    fn uses(code: &str) -> BTreeMap<String, bool> {
+        let tokens = Tokens::new(code);
+        scan(&tokens)
+            .uses
+            .into_values()
+            .map(|x| (x.symbol, x.complete))
+            .collect()
+    }
+
+    #[test]
+    fn incomplete_uses() {
+        let uses = uses(
+            "typedef struct node node_t;\n\
+             typedef node_t *list_t;\n\
+             struct node *first(list_t list);\n\
+             void visit(struct point p);\n\
+             extern struct origin origin;\n\
+             struct tail;\n",
+        );
+
+        for symbol in [
+            "struct node",
+            "node_t",
+            "list_t",
+            "struct point",
+            "struct origin",
+            "struct tail",
+        ] {
+            assert_eq!(uses.get(symbol), Some(&false), "{symbol}");
+        }
+    }
+
+    #[test]
+    fn complete_uses() {
+        let uses = uses(
+            "struct line { struct point from; node_t to; };\n\
+             struct size origin_size(void) { return sizeof(struct origin); }\n\
+             static struct color red;\n",
+        );
+
+        for symbol in [
+            "struct point",
+            "node_t",
+            "struct size",
+            "struct origin",
+            "struct color",
+        ] {
+            assert_eq!(uses.get(symbol), Some(&true), "{symbol}");
+        }
+    }
+}
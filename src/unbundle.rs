@@ -0,0 +1,485 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::bundler::{END_KEY, REMOVED_KEY, RENAMED_KEY, SOURCE_KEY};
+use crate::display::display_path;
+use crate::line_markers;
+use crate::symbols::rename_identifiers;
+
+/// A source file recovered from a bundle.
+#[derive(Debug, Clone)]
+pub struct UnbundledFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+#[derive(Debug, Error)]
+pub enum UnbundleError {
+    #[error("no separators found in bundle (was it made with `separators = true`?)")]
+    NoSeparators,
+
+    #[error("separator at line {line} is not inside a comment")]
+    NotInComment { line: usize },
+
+    #[error("separator at line {line} is never closed")]
+    Unclosed { line: usize },
+
+    #[error("invalid separator at line {line}: {reason}")]
+    InvalidSeparator { line: usize, reason: String },
+
+    #[error("refusing to write to `{}` because it is outside of the output directory", display_path(.0))]
+    OutsideOutput(PathBuf),
+
+    #[error("refusing to overwrite `{}` (use `--force` to overwrite)", display_path(.0))]
+    Exists(PathBuf),
+
+    #[error("failed to write `{}`", display_path(.path))]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct SourceInfo {
+    path: PathBuf,
+    crlf: bool,
+    final_newline: bool,
+    padded: bool,
+    line_markers: bool,
+    removed: Vec<(usize, String)>,
+
+    /// The symbols that were renamed in the bundle, as pairs of the new and
+    /// the original name.
+    renamed: Vec<(String, String)>,
+}
+
+/// Split `bundle` back into the source files it was made of.
+///
+/// This only works for bundles made with separators, because the separators
+/// carry everything needed to rebuild the original files. Everything before
+/// the first separator, like the banner, is ignored.
+pub fn unbundle(bundle: &str) -> Result<Vec<UnbundledFile>, UnbundleError> {
+    let lines: Vec<&str> = bundle.lines().collect();
+
+    // The start of every separator. The content of a source file ends at the
+    // next one, which is either another source file or the end of a section
+    // of generated code.
+    let mut boundaries = Vec::new();
+
+    let mut chunks = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if comment_body(line).starts_with(END_KEY) {
+            let start = lines[..i]
+                .iter()
+                .rposition(|x| x.trim() == "/**")
+                .ok_or(UnbundleError::NotInComment { line: i + 1 })?;
+
+            boundaries.push(start);
+        }
+
+        if comment_body(line).starts_with(SOURCE_KEY) {
+            let start = lines[..i]
+                .iter()
+                .rposition(|x| x.trim() == "/**")
+                .ok_or(UnbundleError::NotInComment { line: i + 1 })?;
+
+            let end = lines[i..]
+                .iter()
+                .position(|x| x.trim() == "*/")
+                .map(|x| x + i)
+                .ok_or(UnbundleError::Unclosed { line: i + 1 })?;
+
+            boundaries.push(start);
+            chunks.push((start, i, end));
+        }
+    }
+
+    if chunks.is_empty() {
+        return Err(UnbundleError::NoSeparators);
+    }
+
+    let mut files = Vec::with_capacity(chunks.len());
+    for (_, info_start, info_end) in &chunks {
+        let info = parse_source_info(&lines[*info_start..*info_end]).map_err(|e| match e {
+            InfoError::Invalid(reason) => UnbundleError::InvalidSeparator {
+                line: info_start + 1,
+                reason,
+            },
+            InfoError::OutsideOutput(x) => UnbundleError::OutsideOutput(x),
+        })?;
+
+        let content_start = match lines.get(info_end + 1) {
+            Some(&"") => info_end + 2,
+            _ => info_end + 1,
+        };
+        let content_end = boundaries
+            .iter()
+            .copied()
+            .find(|x| x > info_end)
+            .unwrap_or(lines.len());
+        let content = &lines[content_start.min(content_end)..content_end];
+
+        files.push(rebuild(info, content));
+    }
+
+    Ok(files)
+}
+
+/// Write `files` under `dir`. Existing files are only overwritten if `force`
+/// is set.
+pub fn write_files(files: &[UnbundledFile], dir: &Path, force: bool) -> Result<(), UnbundleError> {
+    for file in files {
+        let path = dir.join(&file.path);
+
+        if !force && path.exists() {
+            return Err(UnbundleError::Exists(path));
+        }
+
+        let write = || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(&path, &file.content)
+        };
+
+        write().map_err(|source| UnbundleError::Write {
+            path: path.clone(),
+            source,
+        })?;
+
+        info!("wrote {}", display_path(&path));
+    }
+
+    Ok(())
+}
+
+fn rebuild(info: SourceInfo, content: &[&str]) -> UnbundledFile {
+    let mut content: Vec<&str> = content
+        .iter()
+        .copied()
+        .filter(|x| !info.line_markers || !line_markers::is_marker(x))
+        .collect();
+
+    if info.padded && content.last().is_some_and(|x| x.is_empty()) {
+        content.pop();
+    }
+
+    let mut content = content.into_iter();
+    let mut removed = info.removed.iter().peekable();
+    let mut lines = Vec::new();
+
+    for line_no in 1.. {
+        if let Some((_, text)) = removed.next_if(|(x, _)| *x == line_no) {
+            lines.push(text.as_str());
+        } else if let Some(line) = content.next() {
+            lines.push(line);
+        } else if let Some((_, text)) = removed.next() {
+            // The bundle has been edited and lines are missing. Keep the
+            // removed lines anyway.
+            lines.push(text.as_str());
+        } else {
+            break;
+        }
+    }
+
+    let eol = if info.crlf { "\r\n" } else { "\n" };
+    let mut out = lines.join(eol);
+    if info.final_newline && !lines.is_empty() {
+        out.push_str(eol);
+    }
+
+    if !info.renamed.is_empty() {
+        out = rename_identifiers(&out, &info.renamed);
+    }
+
+    UnbundledFile {
+        path: info.path,
+        content: out,
+    }
+}
+
+/// Get the text of a line inside a block comment without the decoration.
+fn comment_body(line: &str) -> &str {
+    let line = line.trim();
+    line.strip_prefix('*').unwrap_or(line).trim_start()
+}
+
+enum InfoError {
+    Invalid(String),
+    OutsideOutput(PathBuf),
+}
+
+impl From<String> for InfoError {
+    fn from(x: String) -> Self {
+        Self::Invalid(x)
+    }
+}
+
+fn parse_source_info(lines: &[&str]) -> Result<SourceInfo, InfoError> {
+    // A formatter might have wrapped long lines, so join every line that does
+    // not start with a key to the previous one.
+    let mut entries: Vec<String> = Vec::new();
+    for line in lines {
+        let body = comment_body(line);
+
+        if [SOURCE_KEY, REMOVED_KEY, RENAMED_KEY]
+            .iter()
+            .any(|x| body.starts_with(x))
+        {
+            entries.push(body.to_owned());
+        } else if let Some(last) = entries.last_mut() {
+            if !body.is_empty() {
+                last.push(' ');
+                last.push_str(body);
+            }
+        }
+    }
+
+    let mut entries = entries.iter();
+
+    let source = entries
+        .next()
+        .and_then(|x| x.strip_prefix(SOURCE_KEY))
+        .ok_or_else(|| format!("missing `{SOURCE_KEY}`"))?;
+    let fields = parse_fields(source)?;
+
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .ok_or_else(|| format!("missing `{key}`"))
+    };
+
+    let flag = |key: &str| match field(key)? {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        x => Err(format!("invalid value `{x}` for `{key}`")),
+    };
+
+    let path = PathBuf::from(field("path")?);
+    if path.as_os_str().is_empty() || !path.components().all(|x| matches!(x, Component::Normal(_)))
+    {
+        return Err(InfoError::OutsideOutput(path));
+    }
+
+    let mut removed = Vec::new();
+    let mut renamed = Vec::new();
+    for entry in entries {
+        let (key, entry) = if let Some(x) = entry.strip_prefix(REMOVED_KEY) {
+            (REMOVED_KEY, x)
+        } else if let Some(x) = entry.strip_prefix(RENAMED_KEY) {
+            (RENAMED_KEY, x)
+        } else {
+            continue;
+        };
+
+        let fields = parse_fields(entry)?;
+        let get = |name: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+                .ok_or_else(|| format!("missing `{name}` in `{key}`"))
+        };
+
+        if key == RENAMED_KEY {
+            renamed.push((get("to")?, get("from")?));
+            continue;
+        }
+
+        let line_no = get("line")?
+            .parse::<usize>()
+            .map_err(|e| format!("invalid line number: {e}"))?;
+        removed.push((line_no, get("text")?));
+    }
+    removed.sort_by_key(|(x, _)| *x);
+
+    Ok(SourceInfo {
+        path,
+        crlf: field("eol")? == "crlf",
+        final_newline: flag("final-newline")?,
+        padded: flag("padded")?,
+        line_markers: flag("line-markers")?,
+        removed,
+        renamed,
+    })
+}
+
+/// Parse space-separated `key=value` pairs where `value` is either a single
+/// word or a quoted string.
+fn parse_fields(s: &str) -> Result<Vec<(String, String)>, String> {
+    let mut fields = Vec::new();
+    let mut iter = s.chars().peekable();
+
+    loop {
+        while iter.next_if(|x| x.is_whitespace()).is_some() {}
+        if iter.peek().is_none() {
+            break;
+        }
+
+        let key: String =
+            std::iter::from_fn(|| iter.next_if(|x| *x != '=' && !x.is_whitespace())).collect();
+        if iter.next() != Some('=') {
+            return Err(format!("expected `=` after `{key}`"));
+        }
+
+        let mut value = String::new();
+        if iter.next_if_eq(&'"').is_some() {
+            loop {
+                match iter.next() {
+                    Some('\\') => value.push(iter.next().ok_or("unterminated string")?),
+                    Some('"') => break,
+                    Some(c) => value.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+        } else {
+            value.extend(std::iter::from_fn(|| iter.next_if(|x| !x.is_whitespace())));
+        }
+
+        fields.push((key, value));
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundler::Mode;
+    use crate::vfs::MemoryFs;
+    use crate::BundleOptions;
+
+    const FILES: &[(&str, &str)] = &[
+        (
+            "lib/util.h",
+            "#ifndef UTIL_H\r\n#define UTIL_H\r\n// cbundl: impl=util.c\r\nint twice(int x); /* */\r\n#endif",
+        ),
+        (
+            "lib/util.c",
+            "// cbundl: bundle\n#include \"util.h\"\n\nint twice(int x) { return 2 * x; }\n\n",
+        ),
+        (
+            "main.c",
+            "// cbundl: bundle\n#include \"lib/util.h\"\n\nint main(void) {\n\treturn twice(0);\n}\n",
+        ),
+    ];
+
+    fn round_trip(mode: Mode, line_markers: bool) {
+        let mut fs = MemoryFs::new();
+        for (path, content) in FILES {
+            fs.insert(Path::new("/src").join(path), *content);
+        }
+
+        let entry = match mode {
+            Mode::Source => "/src/main.c",
+            Mode::SingleHeader | Mode::Amalgamation => "/src/lib/util.h",
+        };
+
+        let bundle = BundleOptions::new(entry)
+            .mode(mode)
+            .banner(false)
+            .format(false)
+            .line_markers(line_markers)
+            .bundle_in(&fs)
+            .unwrap();
+
+        let mut files = unbundle(&bundle.code).unwrap();
+        if let Some(header) = bundle.public_header {
+            files.extend(unbundle(&header.code).unwrap());
+        }
+
+        let mut files: Vec<(String, String)> = files
+            .into_iter()
+            .map(|x| (x.path.to_string_lossy().into_owned(), x.content))
+            .collect();
+        files.sort();
+
+        let mut expected: Vec<(String, String)> = FILES
+            .iter()
+            .filter(|(path, _)| mode == Mode::Source || *path != "main.c")
+            .map(|(path, content)| {
+                let path = match mode {
+                    Mode::Source => path,
+                    _ => path.trim_start_matches("lib/"),
+                };
+                (path.to_owned(), content.to_string())
+            })
+            .collect();
+        expected.sort();
+
+        assert_eq!(files, expected, "{mode:?}");
+    }
+
+    #[test]
+    fn source_round_trip() {
+        round_trip(Mode::Source, false);
+        round_trip(Mode::Source, true);
+    }
+
+    #[test]
+    fn library_round_trip() {
+        round_trip(Mode::SingleHeader, false);
+        round_trip(Mode::Amalgamation, true);
+    }
+
+    #[test]
+    fn renamed_statics_round_trip() {
+        let files = [
+            (
+                "main.c",
+                "// cbundl: bundle\n#include \"a.h\"\n// cbundl: bundle\n#include \"b.h\"\n",
+            ),
+            ("a.h", "int a(void);\n// cbundl: impl=a.c\n"),
+            ("b.h", "int b(void);\n// cbundl: impl=b.c\n"),
+            (
+                "a.c",
+                "static int count; /* count */\nstruct s { int count; };\nint a(void) { return count; }\n",
+            ),
+            ("b.c", "static int count;\nint b(void) { return count; }\n"),
+        ];
+
+        let mut fs = MemoryFs::new();
+        for (path, content) in files {
+            fs.insert(Path::new("/src").join(path), content);
+        }
+
+        let bundle = BundleOptions::new("/src/main.c")
+            .rename_statics(true)
+            .banner(false)
+            .format(false)
+            .bundle_in(&fs)
+            .unwrap();
+
+        assert!(bundle.code.contains("return a_c_count;"), "{}", bundle.code);
+        assert!(bundle.code.contains("return b_c_count;"), "{}", bundle.code);
+
+        let mut unbundled: Vec<(String, String)> = unbundle(&bundle.code)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.path.to_string_lossy().into_owned(), x.content))
+            .collect();
+        unbundled.sort();
+
+        let mut expected: Vec<(String, String)> = files
+            .iter()
+            .map(|(path, content)| ((*path).to_owned(), (*content).to_owned()))
+            .collect();
+        expected.sort();
+
+        assert_eq!(unbundled, expected);
+    }
+
+    #[test]
+    fn bundle_without_separators() {
+        let e = unbundle("int main(void) { return 0; }\n").unwrap_err();
+        assert!(matches!(e, UnbundleError::NoSeparators));
+    }
+}
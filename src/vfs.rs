@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Where source files are read from.
+pub trait FileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /// Get the canonical form of `path`. Two paths that point to the same file
+    /// must have the same canonical form. Fails if `path` does not exist.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.canonicalize(path).is_ok()
+    }
+}
+
+impl<F: FileSystem + ?Sized> FileSystem for &F {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        (**self).read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        (**self).canonicalize(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        (**self).exists(path)
+    }
+}
+
+/// The real filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+}
+
+/// A filesystem that lives entirely in memory.
+///
+/// Paths are compared after `.` and `..` components have been resolved, but
+/// otherwise as they are. This means that `src/a.h` and `/src/a.h` are
+/// different files.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file, or replace it if it already exists.
+    pub fn insert(&mut self, path: impl AsRef<Path>, content: impl Into<String>) {
+        self.files.insert(normalize(path.as_ref()), content.into());
+    }
+
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Option<String> {
+        self.files.remove(&normalize(path.as_ref()))
+    }
+}
+
+impl FromIterator<(PathBuf, String)> for MemoryFs {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, String)>>(iter: I) -> Self {
+        let mut x = Self::new();
+        iter.into_iter()
+            .for_each(|(path, content)| x.insert(path, content));
+        x
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(&normalize(path))
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+
+        if self.files.contains_key(&path) {
+            Ok(path)
+        } else {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        }
+    }
+}
+
+/// Files from `upper` hide the files at the same path in `lower`.
+///
+/// This is useful for bundling files that have been changed but not saved
+/// yet, with a [`MemoryFs`] over a [`RealFs`]. Because a [`MemoryFs`] does
+/// not resolve symlinks or relative paths the way the real filesystem does,
+/// files in it should be added with absolute canonical paths.
+#[derive(Debug, Clone, Default)]
+pub struct OverlayFs<U, L> {
+    pub upper: U,
+    pub lower: L,
+}
+
+impl<U, L> OverlayFs<U, L> {
+    pub fn new(upper: U, lower: L) -> Self {
+        Self { upper, lower }
+    }
+}
+
+impl<U: FileSystem, L: FileSystem> FileSystem for OverlayFs<U, L> {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.upper.read_to_string(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.lower.read_to_string(path),
+            x => x,
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.upper.canonicalize(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.lower.canonicalize(path),
+            x => x,
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.upper.exists(path) || self.lower.exists(path)
+    }
+}
+
+/// Files as they are in a git revision, read with the `git` command line tool.
+///
+/// Relative paths are relative to the current directory, which must be inside
+/// the repository. The working tree is never touched.
+#[derive(Debug, Clone)]
+pub struct GitFs {
+    rev: String,
+    commit: String,
+
+    /// Top-level directory of the working tree.
+    root: PathBuf,
+
+    /// The current directory relative to `root`.
+    prefix: PathBuf,
+}
+
+impl GitFs {
+    /// Open `rev`, which can be anything `git rev-parse` understands, like a
+    /// tag, a branch or a commit hash.
+    pub fn new(rev: impl Into<String>) -> io::Result<Self> {
+        Self::open(".", rev)
+    }
+
+    /// Same as [`GitFs::new`], but for the repository that contains `dir`.
+    /// Relative paths are relative to `dir` instead of the current directory.
+    pub fn open(dir: impl AsRef<Path>, rev: impl Into<String>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let rev = rev.into();
+
+        let root = git_stdout(dir, ["rev-parse", "--show-toplevel"])?;
+        let prefix = git_stdout(dir, ["rev-parse", "--show-prefix"])?;
+        let commit = git_stdout(
+            dir,
+            [
+                "rev-parse",
+                "--verify",
+                "--end-of-options",
+                &format!("{rev}^{{commit}}"),
+            ],
+        )?;
+
+        Ok(Self {
+            rev,
+            commit,
+            root: PathBuf::from(root),
+            prefix: PathBuf::from(prefix),
+        })
+    }
+
+    /// The revision as it was given.
+    pub fn rev(&self) -> &str {
+        &self.rev
+    }
+
+    /// The hash of the commit the revision points to.
+    pub fn commit(&self) -> &str {
+        &self.commit
+    }
+
+    /// Describe the revision for humans, like `v1.0 (0123456789ab)`.
+    pub fn describe(&self) -> String {
+        const SHORT_HASH_LEN: usize = 12;
+
+        let short = &self.commit[..SHORT_HASH_LEN.min(self.commit.len())];
+
+        if self.commit.starts_with(&self.rev) {
+            short.to_owned()
+        } else {
+            format!("{} ({short})", self.rev)
+        }
+    }
+
+    /// Get the `<commit>:<path>` object name of `path`.
+    fn object(&self, path: &Path) -> io::Result<String> {
+        let path = if path.is_absolute() {
+            normalize(path)
+                .strip_prefix(&self.root)
+                .map(Path::to_path_buf)
+                .ok()
+        } else {
+            Some(normalize(&self.prefix.join(path)))
+        }
+        .filter(|x| x.components().all(|x| matches!(x, Component::Normal(_))))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` is outside of the repository", path.display()),
+            )
+        })?;
+
+        let path = path
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Ok(format!("{}:{path}", self.commit))
+    }
+}
+
+impl FileSystem for GitFs {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let object = self.object(path)?;
+        let output = git(&self.root, ["show", "--no-textconv", &object])?;
+
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                git_error(&output.stderr),
+            ));
+        }
+
+        String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let object = self.object(path)?;
+        let output = git(&self.root, ["cat-file", "-e", &object])?;
+
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` does not exist in `{}`", path.display(), self.rev),
+            ));
+        }
+
+        let (_, path) = object.split_once(':').expect("object names contain a `:`");
+        Ok(self.root.join(path))
+    }
+}
+
+fn git<I, S>(dir: &Path, args: I) -> io::Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run git: {e}")))
+}
+
+/// Run git and get the first line of its output.
+fn git_stdout<I, S>(dir: &Path, args: I) -> io::Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = git(dir, args)?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(git_error(&output.stderr)));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().to_owned())
+}
+
+fn git_error(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    stderr.strip_prefix("fatal: ").unwrap_or(stderr).to_owned()
+}
+
+/// Resolve `.` and `..` components of `path` without touching the filesystem.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                Some(Component::CurDir | Component::ParentDir) | None => out.push(".."),
+            },
+            x => out.push(x),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{self, AtomicUsize};
+
+    use super::*;
+    use crate::header::HeaderSource;
+    use crate::BundleOptions;
+
+    /// A git repository in a temporary directory, removed on drop.
+    struct Repo(PathBuf);
+
+    impl Repo {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let root = std::env::temp_dir().join(format!(
+                "cbundl-git-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, atomic::Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&root).unwrap();
+
+            let repo = Self(fs::canonicalize(root).unwrap());
+            repo.git(&["init", "-q"]);
+            repo
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            fs::write(self.0.join(path), content).unwrap();
+        }
+
+        fn git(&self, args: &[&str]) {
+            let output = git(
+                &self.0,
+                [
+                    "-c",
+                    "user.name=cbundl",
+                    "-c",
+                    "user.email=cbundl@localhost",
+                    "-c",
+                    "commit.gpgsign=false",
+                ]
+                .iter()
+                .chain(args),
+            )
+            .unwrap();
+
+            assert!(output.status.success(), "{}", git_error(&output.stderr));
+        }
+    }
+
+    impl Drop for Repo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn memory_paths_are_normalized() {
+        let mut fs = MemoryFs::new();
+        fs.insert("/src/./lib/../a.h", "int a;");
+
+        let path = Path::new("/src/a.h");
+        assert_eq!(fs.read_to_string(path).unwrap(), "int a;");
+        assert_eq!(fs.canonicalize(Path::new("/src/lib/../a.h")).unwrap(), path);
+
+        assert!(!fs.exists(Path::new("src/a.h")));
+        assert_eq!(normalize(Path::new("../a/../../b")), Path::new("../../b"));
+        assert_eq!(normalize(Path::new("/../a")), Path::new("/a"));
+
+        assert_eq!(fs.remove("/src/a.h").as_deref(), Some("int a;"));
+        assert!(!fs.exists(path));
+    }
+
+    #[test]
+    fn overlay_upper_hides_lower() {
+        let mut upper = MemoryFs::new();
+        upper.insert("/src/a.h", "upper");
+
+        let mut lower = MemoryFs::new();
+        lower.insert("/src/a.h", "lower");
+        lower.insert("/src/b.h", "lower");
+
+        let fs = OverlayFs::new(upper, lower);
+        assert_eq!(fs.read_to_string(Path::new("/src/a.h")).unwrap(), "upper");
+        assert_eq!(fs.read_to_string(Path::new("/src/b.h")).unwrap(), "lower");
+        assert!(fs.exists(Path::new("/src/b.h")));
+
+        let e = fs.read_to_string(Path::new("/src/c.h")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn git_files_come_from_the_revision() {
+        let repo = Repo::new();
+        repo.write("main.c", "int main(void) { return 0; }\n");
+        repo.write("header.txt", "/* old header */\n");
+        repo.git(&["add", "-A"]);
+        repo.git(&["commit", "-q", "-m", "old"]);
+        repo.git(&["tag", "old"]);
+
+        repo.write("main.c", "int main(void) { return 1; }\n");
+        repo.write("header.txt", "/* new header */\n");
+
+        let fs = GitFs::open(&repo.0, "old").unwrap();
+        assert_eq!(fs.rev(), "old");
+        assert_eq!(
+            fs.read_to_string(Path::new("main.c")).unwrap(),
+            "int main(void) { return 0; }\n"
+        );
+        assert_eq!(
+            fs.canonicalize(&repo.0.join("./main.c")).unwrap(),
+            repo.0.join("main.c")
+        );
+        assert!(!fs.exists(Path::new("missing.h")));
+        assert!(!fs.exists(Path::new("../main.c")));
+
+        let bundle = BundleOptions::new(repo.0.join("main.c"))
+            .banner(false)
+            .format(false)
+            .header(Some(HeaderSource::File(repo.0.join("header.txt"))))
+            .bundle_in(&fs)
+            .unwrap();
+
+        assert!(
+            bundle.code.starts_with("/* old header */\n"),
+            "{}",
+            bundle.code
+        );
+        assert!(bundle.code.contains("return 0;"), "{}", bundle.code);
+    }
+}
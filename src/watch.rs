@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use eyre::{eyre, Context, Result};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use cbundl::display::display_path;
+
+/// How long to wait for things to settle down after a change before
+/// rebuilding. Editors tend to touch a file a few times when saving it.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Run `build` every time one of the files it depends on changes.
+///
+/// `build` returns the files it depends on. If it fails, the files from the
+/// last successful build and `roots` are watched instead, and a file that
+/// appears next to them also triggers a rebuild. This way a header that was
+/// missing gets picked up as soon as it is created.
+pub fn watch<F>(roots: &[PathBuf], mut build: F) -> Result<()>
+where
+    F: FnMut() -> Result<Vec<PathBuf>>,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("failed to start file watcher")?;
+
+    let mut files: HashSet<PathBuf> = roots.iter().map(|x| canonicalize(x)).collect();
+    let mut dirs: HashSet<PathBuf> = HashSet::new();
+    let mut changed: Option<PathBuf> = None;
+
+    loop {
+        let start = Instant::now();
+        let ok = match build() {
+            Ok(x) => {
+                files = x.iter().chain(roots).map(|x| canonicalize(x)).collect();
+
+                let reason = match changed.as_ref() {
+                    Some(x) => format!("rebuilt after change to `{}`", display_path(x)),
+                    None => "built".to_owned(),
+                };
+
+                info!(
+                    "{reason} in {:.2?}, watching {} files",
+                    start.elapsed(),
+                    files.len()
+                );
+                true
+            }
+            Err(e) => {
+                crate::log_error(&e);
+                false
+            }
+        };
+
+        let new_dirs: HashSet<PathBuf> = files
+            .iter()
+            .filter_map(|x| x.parent())
+            .map(Path::to_path_buf)
+            .collect();
+
+        update_watches(&mut watcher, &dirs, &new_dirs);
+        dirs = new_dirs;
+
+        let filter = Filter {
+            files: &files,
+            last_build_ok: ok,
+        };
+        changed = Some(wait_for_change(&rx, &filter)?);
+    }
+}
+
+fn update_watches(
+    watcher: &mut RecommendedWatcher,
+    old: &HashSet<PathBuf>,
+    new: &HashSet<PathBuf>,
+) {
+    for dir in old.difference(new) {
+        // The directory might not exist anymore.
+        let _ = watcher.unwatch(dir);
+    }
+
+    for dir in new.difference(old) {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            warn!("failed to watch `{}`: {e}", display_path(dir));
+        }
+    }
+}
+
+/// Decides which changes should trigger a rebuild.
+struct Filter<'a> {
+    /// The files the last build depends on.
+    files: &'a HashSet<PathBuf>,
+    last_build_ok: bool,
+}
+
+impl Filter<'_> {
+    /// Get the path in `event` that should trigger a rebuild, if any.
+    ///
+    /// A change to one of the files always matters. After a failed build, a
+    /// new file also matters because it might be a header that was missing.
+    /// Hidden files and backups, which editors create all the time, never do.
+    fn matches(&self, event: Event) -> Option<PathBuf> {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return None;
+        }
+
+        let created = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+        );
+
+        event.paths.into_iter().find(|x| {
+            self.files.contains(x) || (!self.last_build_ok && created && !is_scratch_file(x))
+        })
+    }
+}
+
+/// Check if `path` is a file editors create next to the files being edited,
+/// like `.main.c.swp` or `main.c~`.
+fn is_scratch_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy())
+        .unwrap_or_default();
+
+    name.starts_with('.') || name.ends_with('~')
+}
+
+/// Block until a change that passes `filter`, then wait for things to settle
+/// down. Returns the path that changed.
+fn wait_for_change(rx: &Receiver<notify::Result<Event>>, filter: &Filter<'_>) -> Result<PathBuf> {
+    let changed = loop {
+        let event = match rx.recv() {
+            Ok(Ok(x)) => x,
+            Ok(Err(e)) => {
+                warn!("file watcher error: {e}");
+                continue;
+            }
+            Err(_) => return Err(eyre!("file watcher stopped unexpectedly")),
+        };
+
+        if let Some(x) = filter.matches(event) {
+            break x;
+        }
+    };
+
+    while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+    Ok(changed)
+}
+
+fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{AccessKind, CreateKind, DataChange};
+
+    use super::*;
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(PathBuf::from(path))
+    }
+
+    fn modify(path: &str) -> Event {
+        event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), path)
+    }
+
+    fn create(path: &str) -> Event {
+        event(EventKind::Create(CreateKind::File), path)
+    }
+
+    #[test]
+    fn filter() {
+        let files = HashSet::from([PathBuf::from("/src/main.c")]);
+        let ok = Filter {
+            files: &files,
+            last_build_ok: true,
+        };
+        let failed = Filter {
+            files: &files,
+            last_build_ok: false,
+        };
+
+        for filter in [&ok, &failed] {
+            assert_eq!(
+                filter.matches(modify("/src/main.c")),
+                Some(PathBuf::from("/src/main.c"))
+            );
+            assert_eq!(
+                filter.matches(event(EventKind::Access(AccessKind::Any), "/src/main.c")),
+                None
+            );
+            assert_eq!(filter.matches(modify("/src/bundle.c")), None);
+            assert_eq!(filter.matches(create("/src/.main.c.swp")), None);
+            assert_eq!(filter.matches(create("/src/main.c~")), None);
+        }
+
+        assert_eq!(ok.matches(create("/src/a.h")), None);
+        assert_eq!(
+            failed.matches(create("/src/a.h")),
+            Some(PathBuf::from("/src/a.h"))
+        );
+        assert_eq!(
+            failed.matches(event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+                "/src/a.h"
+            )),
+            Some(PathBuf::from("/src/a.h"))
+        );
+    }
+
+    #[test]
+    fn changes_are_debounced() {
+        let files = HashSet::from([PathBuf::from("/src/a.h"), PathBuf::from("/src/b.h")]);
+        let filter = Filter {
+            files: &files,
+            last_build_ok: true,
+        };
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(Ok(modify("/src/bundle.c"))).unwrap();
+        tx.send(Ok(modify("/src/a.h"))).unwrap();
+        tx.send(Ok(modify("/src/b.h"))).unwrap();
+        tx.send(Ok(modify("/src/a.h"))).unwrap();
+
+        assert_eq!(
+            wait_for_change(&rx, &filter).unwrap(),
+            PathBuf::from("/src/a.h")
+        );
+        assert!(rx.try_recv().is_err());
+
+        drop(tx);
+        assert!(wait_for_change(&rx, &filter).is_err());
+    }
+}